@@ -1,4 +1,14 @@
-use crate::{config::ProjectConfig, file_actions::recurse_copy, link::*, ProjectContext};
+use crate::{
+    backup,
+    config::ProjectConfig,
+    file_actions::{copy_tree, recurse_copy, sync_tree, walk_files},
+    history::{self, Operation},
+    ignore::IgnoreRules,
+    journal::{self, MoveStage, PendingMove},
+    link::*,
+    util::expand_tilde,
+    ProjectContext,
+};
 use anyhow::{bail, Context, Result};
 use cascade::cascade;
 use itertools::Itertools;
@@ -6,6 +16,10 @@ use log::*;
 use std::path::{Path, PathBuf};
 use tokio::fs;
 
+fn is_glob(path: &str) -> bool {
+    path.contains(&['*', '?', '['][..])
+}
+
 pub async fn add(
     ctx: &ProjectContext,
     //File to copy
@@ -13,11 +27,13 @@ pub async fn add(
     //Location of where to place it in the project
     destination: Option<String>,
     name: Option<String>,
+    recursive: bool,
+    exclude: Vec<String>,
 ) -> Result<ProjectConfig> {
     if original_locations.is_empty() {
         bail!("No files defined to link");
     }
-    if original_locations.len() == 1 {
+    if original_locations.len() == 1 && !recursive && !is_glob(&original_locations[0]) {
         add_individual_link(
             ctx,
             original_locations.first().unwrap().clone(),
@@ -26,10 +42,115 @@ pub async fn add(
         )
         .await
     } else {
-        manage_list(ctx, original_locations, destination).await
+        let exclude: Vec<_> = exclude
+            .iter()
+            .map(|x| glob::Pattern::new(x).context(format!("Invalid exclude pattern {}", x)))
+            .try_collect()?;
+        let mut entries = Vec::new();
+        for location in original_locations.into_iter().dedup() {
+            entries.extend(expand_location(ctx, &location, recursive, &exclude)?);
+        }
+        if entries.is_empty() {
+            bail!("No files matched");
+        }
+        manage_list(ctx, entries, destination).await
     }
 }
 
+/// A file to be moved into the project
+#[derive(Debug)]
+struct AddEntry {
+    //Realised location of the file
+    cleaned: PathBuf,
+    //Location relative to the destination folder in the project
+    relative: PathBuf,
+    //Location to link back to, with variables kept
+    destination: VariablePath,
+}
+
+/// Expand `~`, globs and with `recursive` folders, keeping the layout relative to the
+/// non-glob part of the location
+fn expand_location(
+    ctx: &ProjectContext,
+    location: &str,
+    recursive: bool,
+    exclude: &[glob::Pattern],
+) -> Result<Vec<AddEntry>> {
+    let location = expand_tilde(location);
+    //Append current directory if it is a generic location
+    let location = if location.starts_with('$') || Path::new(&location).has_root() {
+        location
+    } else {
+        format!("{}/{}", std::env::current_dir()?.display(), location)
+    };
+    let variables = ctx.project.variables.as_ref();
+
+    let (base, matches): (String, Vec<PathBuf>) = if is_glob(&location) {
+        let components: Vec<_> = location.split('/').collect();
+        let split = components
+            .iter()
+            .position(|x| is_glob(x))
+            .context("Could not find glob")?;
+        let base = components[..split].join("/");
+        let expanded_base = VariablePath::from(&base).to_path_buf(variables)?;
+        let pattern = expanded_base.join(components[split..].join("/"));
+        let matches = glob::glob(pattern.to_str().context("Invalid pattern")?)?
+            .map(|x| {
+                let path = x?;
+                Ok(PathBuf::from(&base).join(path.strip_prefix(&expanded_base)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        (base, matches)
+    } else {
+        let (base, _) = location
+            .rsplit_once('/')
+            .context(format!("Could not get parent folder of {}", location))?;
+        (base.to_string(), vec![PathBuf::from(&location)])
+    };
+
+    let mut entries = Vec::new();
+    for path in matches {
+        let relative = path.strip_prefix(&base)?.to_path_buf();
+        let expanded = VariablePath::from(path.to_string_lossy()).to_path_buf(variables)?;
+        let metadata = expanded
+            .symlink_metadata()
+            .context(format!(r#"file "{}" could not be found"#, path.display()))?;
+        let files = if metadata.file_type().is_symlink() {
+            warn!("Skipping symlink {}", path.display());
+            continue;
+        } else if recursive && metadata.is_dir() {
+            walk_files(&expanded)?
+                .into_iter()
+                .map(|x| Ok(relative.join(x.strip_prefix(&expanded)?)))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            vec![relative]
+        };
+        for relative in files {
+            if exclude.iter().any(|x| {
+                x.matches_path(&relative)
+                    || relative
+                        .file_name()
+                        .and_then(|y| y.to_str())
+                        .map_or(false, |y| x.matches(y))
+            }) {
+                debug!("excluding {}", relative.display());
+                continue;
+            }
+            let destination = PathBuf::from(&base).join(&relative);
+            let cleaned = VariablePath::from(destination.to_string_lossy())
+                .to_path_buf(variables)?
+                .canonicalize()?;
+            entries.push(AddEntry {
+                cleaned,
+                relative,
+                destination: destination.to_string_lossy().into(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
 async fn add_individual_link(
     ctx: &ProjectContext,
     mut original_location: String,
@@ -96,10 +217,7 @@ async fn add_individual_link(
                 .project_config_path
                 .join(&output_dest)
                 .canonicalize()
-                .map(|x| {
-                    println!("{}", x.display());
-                    x.exists()
-                })
+                .map(|x| x.exists())
                 .unwrap_or(false)
             || ctx
                 .project
@@ -153,8 +271,19 @@ async fn add_individual_link(
         debug!("name is orig: {}, source: {}", original_location, source);
         completed_links.push(Link::new(name.clone(), original_location, source));
     };
+    let link_style = completed_links
+        .iter()
+        .find(|x| x.src.contains_path(&output_dest))
+        .map(|x| ctx.project.link_style(x))
+        .unwrap_or_default();
 
     let output_dest = ctx.project_config_path.join(output_dest);
+    let ignore = ctx.ignore_rules()?;
+    anyhow::ensure!(
+        !ignore.is_ignored_within(&output_dest, original_location_cleaned.is_dir()),
+        "{} is ignored",
+        output_dest.display()
+    );
     let final_project_config = cascade! {
         ctx.project.clone();
         ..links = completed_links;
@@ -165,47 +294,80 @@ async fn add_individual_link(
             .context("Could not get parent folder")?,
     )
     .await?;
-    move_link(&original_location_cleaned, &output_dest).await?;
+    move_link(
+        &original_location_cleaned,
+        &output_dest,
+        &ignore,
+        link_style,
+    )
+    .await?;
     info!("Added {}", name);
     Ok(final_project_config)
 }
 
 async fn manage_list(
     ctx: &ProjectContext,
-    locations: Vec<String>,
+    entries: Vec<AddEntry>,
     destination: Option<String>,
 ) -> Result<ProjectConfig> {
-    let dest = destination.unwrap_or_else(|| String::from("."));
-    fs::create_dir_all(ctx.project_config_path.join(&dest)).await?;
-    let mut triples: Vec<_> = locations
-        .into_iter()
-        .dedup()
-        .map(|mut path| {
-            let p = PathBuf::from(&path);
-            if p.exists() && !p.has_root() {
-                path = format!("{}/{}", std::env::current_dir()?.display(), path)
-            };
-            let variable_path: VariablePath = path.clone().into();
-            let cleaned = variable_path
-                .to_path_buf(ctx.project.variables.as_ref())?
-                .canonicalize()
-                .context(format!(r#"file "{}" could not be found"#, &path))?;
-
-            let file_name: String = cleaned
-                .file_name()
-                .map(|x| x.to_string_lossy())
-                .context("Could not get file name")?
-                .into();
-            let dest_file = format!("{}/{}", dest, file_name);
+    let dest = match destination.map(PathBuf::from) {
+        Some(destination) => match destination.strip_prefix(&ctx.project_config_path) {
+            Ok(x) => x.to_path_buf(),
+            _ => destination,
+        },
+        None => PathBuf::new(),
+    };
+    let variables = ctx.project.variables.as_ref();
+    let links_to = |link: &Link, entry: &AddEntry| {
+        link.destination == entry.destination
+            || link
+                .destination
+                .to_path_buf(variables)
+                .and_then(|x| Ok(same_file::is_same_file(x, &entry.cleaned)?))
+                .unwrap_or(false)
+    };
+    let ignore = ctx.ignore_rules()?;
+    let mut names: Vec<String> = ctx.project.links.iter().map(|x| x.name.clone()).collect();
+    let mut planned = Vec::new();
+    for entry in entries {
+        let dest_file = dest.join(&entry.relative).to_string_lossy().to_string();
+        if ignore.is_ignored_within(Path::new(&dest_file), entry.cleaned.is_dir()) {
+            info!("Skipping ignored {}", entry.cleaned.display());
+            continue;
+        }
+        anyhow::ensure!(
+            !ctx.project_config_path.join(&dest_file).exists()
+                && !ctx
+                    .project
+                    .links
+                    .iter()
+                    .any(|x| x.src.contains_path(&dest_file)),
+            "file {} already exists",
+            dest_file
+        );
+        anyhow::ensure!(
+            !planned
+                .iter()
+                .any(|(_, x, _): &(AddEntry, String, bool)| x == &dest_file),
+            "{} matched twice",
+            dest_file
+        );
+        //Links that already point at this location get a new variant for the system
+        let existing = ctx.project.links.iter().any(|link| links_to(link, &entry));
+        if !existing {
+            let name = entry.relative.to_string_lossy().to_string();
             anyhow::ensure!(
-                ctx.project_config_path.join(&dest_file).exists(),
-                "file {} already exists",
-                dest_file
+                !names.contains(&name),
+                "links already contain link of name {}",
+                name
             );
-
-            Ok((false, cleaned, dest_file, variable_path, file_name))
-        })
-        .try_collect()?;
+            names.push(name);
+        }
+        planned.push((entry, dest_file, existing));
+    }
+    if planned.is_empty() {
+        bail!("Every matched file is ignored");
+    }
 
     let get_system = || ctx.args.system.to_owned().context("could not get system");
 
@@ -215,15 +377,12 @@ async fn manage_list(
         .iter()
         .cloned()
         .map(|mut link| {
-            let (_, _, dest_file, _, _) = match triples
-                .iter_mut()
-                .find(|(f, _, _, p, _)| p == &link.destination && !f)
+            let dest_file = match planned
+                .iter()
+                .find(|(entry, _, existing)| *existing && links_to(&link, entry))
             {
                 None => return Ok(link),
-                Some(x) => {
-                    x.0 = true;
-                    x
-                }
+                Some((_, dest_file, _)) => dest_file,
             };
             let sys = get_system()?;
             link.src = link.src.insert_link(&sys, dest_file)?;
@@ -232,23 +391,55 @@ async fn manage_list(
         })
         .try_collect()?;
 
-    for (_, cleaned, dest_file, _, name) in &triples {
-        info!("Linked {}", name);
+    //Move every file, putting back the ones already moved if one fails
+    let mut moved: Vec<(&Path, PathBuf)> = Vec::new();
+    for (entry, dest_file, _) in &planned {
+        let output_dest = ctx.project_config_path.join(dest_file);
+        let link_style = new_links
+            .iter()
+            .find(|x| x.src.contains_path(dest_file))
+            .map(|x| ctx.project.link_style(x))
+            .unwrap_or_else(|| ctx.project.link_style.unwrap_or_default());
         debug!(
             "cleaned = {}, dest_file = {}",
-            cleaned.display(),
-            ctx.project_config_path.join(dest_file).display()
+            entry.cleaned.display(),
+            output_dest.display()
         );
-        move_link(cleaned, &ctx.project_config_path.join(dest_file)).await?;
+        let result = async {
+            fs::create_dir_all(
+                output_dest
+                    .parent()
+                    .context("Could not get parent folder")?,
+            )
+            .await?;
+            move_link(&entry.cleaned, &output_dest, &ignore, link_style).await
+        }
+        .await;
+        if let Err(e) = result {
+            for (original, output_dest) in moved.into_iter().rev() {
+                if let Err(e) = restore_link(original, &output_dest).await {
+                    error!("Could not restore {}: {}", original.display(), e);
+                }
+            }
+            return Err(e.context(format!("Failed adding {}", entry.cleaned.display())));
+        }
+        info!("Linked {}", entry.relative.display());
+        moved.push((&entry.cleaned, output_dest));
     }
 
-    for (_, _, dest_file, p, name) in triples.into_iter().filter(|x| x.0) {
+    for (entry, dest_file, existing) in planned {
+        if existing {
+            continue;
+        }
         let source = SourceFile::Source {
             system: ctx.args.system.clone(),
             src: dest_file,
         };
-
-        new_links.push(Link::new(name.to_string(), p, source));
+        new_links.push(Link::new(
+            entry.relative.to_string_lossy().to_string(),
+            entry.destination,
+            source,
+        ));
     }
 
     let new_project = cascade! {
@@ -258,23 +449,124 @@ async fn manage_list(
     Ok(new_project)
 }
 
-async fn move_link(original_locaction_cleaned: &Path, output_dest: &Path) -> Result<()> {
-    if original_locaction_cleaned.is_dir() {
-        recurse_copy(original_locaction_cleaned, output_dest).await?;
-    } else {
-        fs::copy(original_locaction_cleaned, output_dest).await?;
+/// Undo `move_link`, putting the file back at its original location
+async fn restore_link(original_location: &Path, output_dest: &Path) -> Result<()> {
+    fs::remove_file(original_location).await?;
+    if fs::rename(output_dest, original_location).await.is_err() {
+        recurse_copy(output_dest, original_location).await?;
+        if output_dest.is_dir() {
+            fs::remove_dir_all(output_dest).await?;
+        } else {
+            fs::remove_file(output_dest).await?;
+        }
     }
-    if fs::metadata(original_locaction_cleaned).await?.is_dir() {
-        fs::remove_dir_all(original_locaction_cleaned).await?;
-    } else {
-        fs::remove_file(original_locaction_cleaned).await?;
+    Ok(())
+}
+
+/// Whether anything inside `dir` would be left out when copied to `output_dest`
+fn contains_ignored(dir: &Path, output_dest: &Path, ignore: &IgnoreRules) -> Result<bool> {
+    for file in walk_files(dir)? {
+        let relative = file.strip_prefix(dir)?;
+        if ignore.is_ignored_within(&output_dest.join(relative), false) {
+            return Ok(true);
+        }
     }
-    debug!(
-        "loc = {} \n dest = {}",
-        original_locaction_cleaned.display(),
-        output_dest.display()
-    );
+    Ok(false)
+}
+
+/// Move a file or folder into the project and link it back, leaving out ignored files.
+/// Every step is recorded in the journal, so an interrupted move is finished or rolled back by
+/// the next run.
+pub async fn move_link(
+    original_locaction_cleaned: &Path,
+    output_dest: &Path,
+    ignore: &IgnoreRules,
+    style: LinkStyle,
+) -> Result<()> {
+    backup::snapshot(original_locaction_cleaned, "add")?;
+    let mut temp = output_dest.as_os_str().to_owned();
+    temp.push(".dots-tmp");
+    let mut pending = PendingMove {
+        original: original_locaction_cleaned.to_path_buf(),
+        destination: output_dest.to_path_buf(),
+        temp: PathBuf::from(temp),
+        stage: MoveStage::Moving,
+        style,
+    };
+    journal::record(&pending)?;
+    let result = async {
+        move_into_place(&pending, ignore).await?;
+        pending.stage = MoveStage::Moved;
+        journal::record(&pending)?;
+        if fs::symlink_metadata(original_locaction_cleaned)
+            .await
+            .is_ok()
+        {
+            if fs::metadata(original_locaction_cleaned).await?.is_dir() {
+                fs::remove_dir_all(original_locaction_cleaned).await?;
+            } else {
+                fs::remove_file(original_locaction_cleaned).await?;
+            }
+        }
+        debug!(
+            "loc = {} \n dest = {}",
+            original_locaction_cleaned.display(),
+            output_dest.display()
+        );
+        fs::symlink(
+            style.target(output_dest, original_locaction_cleaned),
+            original_locaction_cleaned,
+        )
+        .await?;
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+    match result {
+        Ok(_) => {
+            history::record(Operation::Move {
+                from: original_locaction_cleaned.to_path_buf(),
+                to: output_dest.to_path_buf(),
+            });
+            history::record(Operation::Create {
+                path: original_locaction_cleaned.to_path_buf(),
+            });
+            journal::finish(original_locaction_cleaned)
+        }
+        Err(e) => {
+            if let Err(e) = journal::settle(&pending) {
+                error!(
+                    "Could not recover {}: {}",
+                    original_locaction_cleaned.display(),
+                    e
+                );
+            }
+            Err(e)
+        }
+    }
+}
 
-    fs::symlink(output_dest, original_locaction_cleaned).await?;
+/// Rename the original into the project when possible, otherwise copy it next to the
+/// destination, sync it to disk and rename it into place
+async fn move_into_place(pending: &PendingMove, ignore: &IgnoreRules) -> Result<()> {
+    let PendingMove {
+        original,
+        destination,
+        temp,
+        ..
+    } = pending;
+    let is_dir = original.is_dir();
+    if !is_dir || !contains_ignored(original, destination, ignore)? {
+        match fs::rename(original, destination).await {
+            Ok(_) => return Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    copy_tree(original, temp, ignore).await?;
+    sync_tree(temp)?;
+    fs::rename(temp, destination).await?;
+    if let Some(parent) = destination.parent() {
+        std::fs::File::open(parent)?.sync_all()?;
+    }
     Ok(())
 }
@@ -0,0 +1,126 @@
+use super::add::move_link;
+use crate::{
+    config::ProjectConfig,
+    diff::diff_paths,
+    link::{Link, SourceFile, System},
+    privileged::Escalation,
+    util::confirm,
+    ProjectContext,
+};
+use anyhow::{Context, Result};
+use colored::*;
+use log::*;
+use tokio::fs;
+
+/// Move files already at link destinations into the project as variants for the current system
+pub async fn adopt(ctx: &ProjectContext, yes: bool) -> Result<ProjectConfig> {
+    let system = ctx
+        .system
+        .clone()
+        .context("Adopting requires a system, set one with --system")?;
+    let mut project = ctx.project.clone();
+    for link in project.links.iter_mut() {
+        match adopt_link(ctx, link, &system, yes).await {
+            Ok(Some(src)) => link.src = src,
+            Ok(None) => {}
+            Err(e) => error!("Failed adopting {}: {}", link.name, e),
+        }
+    }
+    Ok(project)
+}
+
+async fn adopt_link(
+    ctx: &ProjectContext,
+    link: &Link,
+    system: &System,
+    yes: bool,
+) -> Result<Option<SourceFile>> {
+    let current = match link
+        .src
+        .resolve(&Some(system.clone()))
+        .or_else(|| link.src.clone().into_iter().next().map(|x| x.2))
+    {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let current_path = ctx.project_config_path.join(&current);
+
+    let mut destination = link
+        .destination
+        .to_path_buf(ctx.project.variables.as_ref())?;
+    if destination.is_dir() && !current_path.is_dir() {
+        destination.push(
+            current_path
+                .file_name()
+                .context(format!("Could not get file name for {}", link.name))?,
+        );
+    }
+    match destination.symlink_metadata() {
+        Ok(metadata) if !metadata.file_type().is_symlink() => {}
+        _ => return Ok(None),
+    }
+    if link
+        .src
+        .clone()
+        .into_iter()
+        .any(|(_, sys, _)| sys.as_ref() == Some(system))
+    {
+        warn!(
+            "{} already has a source for {}, skipping {}",
+            link.name,
+            system,
+            destination.display()
+        );
+        return Ok(None);
+    }
+    if ctx.escalation(&destination, link.sudo_required) != Escalation::NotNeeded {
+        warn!(
+            "Skipping {}, adopting files that require sudo is not supported",
+            destination.display()
+        );
+        return Ok(None);
+    }
+
+    let variant = format!("{}.{}", current.trim_end_matches('/'), system);
+    let variant_path = ctx.project_config_path.join(&variant);
+    anyhow::ensure!(
+        !variant_path.exists(),
+        "{} already exists in the project",
+        variant
+    );
+    let new_src = link.src.clone().insert_link(system, &variant)?;
+
+    println!("{} {}", "Adopting".bold(), destination.display());
+    if current_path.exists() {
+        match diff_paths(&current_path, &destination)? {
+            Some(diff) => print!("{}", diff),
+            None => println!("Same as {}", current),
+        }
+    }
+    if !yes
+        && !confirm(&format!(
+            "Move {} into the project as {}?",
+            destination.display(),
+            variant
+        ))?
+    {
+        info!("Skipped {}", destination.display());
+        return Ok(None);
+    }
+
+    fs::create_dir_all(
+        variant_path
+            .parent()
+            .context("Could not get parent folder")?,
+    )
+    .await?;
+    move_link(
+        &destination,
+        &variant_path,
+        &ctx.ignore_rules()?,
+        ctx.project.link_style(link),
+    )
+    .await?;
+    info!("Adopted {} as {}", destination.display(), variant);
+    Ok(Some(new_src))
+}
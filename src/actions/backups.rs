@@ -0,0 +1,113 @@
+use crate::backup::{self, entry_path, BackupStore, EntryKind};
+use crate::config::SystemConfig;
+use crate::history::{self, Operation};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::*;
+use log::*;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Parser, Clone)]
+pub enum BackupsSubCommand {
+    #[clap(about = "List backups, optionally only the ones of a path")]
+    List { path: Option<PathBuf> },
+    #[clap(about = "Show what a backup contains")]
+    Show { id: String },
+    #[clap(about = "Put a backup back where it was taken from, or somewhere else")]
+    Restore {
+        id: String,
+        #[clap(long, about = "Restore to this path instead")]
+        to: Option<PathBuf>,
+        #[clap(
+            short,
+            long,
+            about = "Replace whatever is at the path, backing it up first"
+        )]
+        force: bool,
+    },
+    #[clap(about = "Remove backups past the retention set in the system config")]
+    Gc,
+}
+
+pub fn backups(system_config: &SystemConfig, command: BackupsSubCommand) -> Result<()> {
+    let store = BackupStore::open()?;
+    use BackupsSubCommand::*;
+    match command {
+        List { path } => {
+            let path = path.map(|x| x.canonicalize().unwrap_or(x));
+            for backup in store.list()? {
+                if path.as_ref().map_or(false, |x| x != &backup.path) {
+                    continue;
+                }
+                println!(
+                    "{} {} {} {}",
+                    backup.id.yellow(),
+                    humantime::format_rfc3339_seconds(backup.time()),
+                    backup.reason.bold(),
+                    backup.path.display()
+                );
+            }
+        }
+        Show { id } => {
+            let backup = store.get(&id)?;
+            println!("{} {}", "Backup".bold(), backup.id.yellow());
+            println!("Path: {}", backup.path.display());
+            println!(
+                "Taken: {}",
+                humantime::format_rfc3339_seconds(backup.time())
+            );
+            println!("Before: {}", backup.reason);
+            for entry in &backup.entries {
+                let path = entry_path(&backup.path, &entry.path);
+                match entry.kind {
+                    EntryKind::Dir => println!("{:o} {}/", entry.mode & 0o7777, path.display()),
+                    EntryKind::File => println!(
+                        "{:o} {} ({} bytes)",
+                        entry.mode & 0o7777,
+                        path.display(),
+                        entry.size.unwrap_or_default()
+                    ),
+                    EntryKind::Symlink => println!(
+                        "{} -> {}",
+                        path.display(),
+                        entry.target.clone().unwrap_or_default().display()
+                    ),
+                }
+            }
+            if let [entry] = &backup.entries[..] {
+                if entry.kind == EntryKind::File {
+                    match String::from_utf8(store.read(entry)?) {
+                        Ok(text) => print!("\n{}", text),
+                        Err(_) => println!("Binary file"),
+                    }
+                }
+            }
+        }
+        Restore { id, to, force } => {
+            let backup = store.get(&id)?;
+            let to = to.unwrap_or_else(|| backup.path.clone());
+            if force {
+                if let Ok(metadata) = to.symlink_metadata() {
+                    backup::snapshot(&to, "restore")?;
+                    if metadata.is_dir() {
+                        fs::remove_dir_all(&to)?;
+                    } else {
+                        fs::remove_file(&to)?;
+                    }
+                }
+            }
+            store
+                .restore(&backup, &to)
+                .context(format!("Could not restore {}", backup.id))?;
+            history::record(Operation::Create { path: to.clone() });
+            info!("Restored {} to {}", backup.id, to.display());
+        }
+        Gc => {
+            let (backups, objects) =
+                store.gc(&system_config.backups.clone().unwrap_or_default())?;
+            info!("Removed {} backups and {} stored files", backups, objects);
+        }
+    }
+    Ok(())
+}
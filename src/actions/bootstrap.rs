@@ -0,0 +1,160 @@
+use super::{manage, sync};
+use crate::{
+    file_actions::recurse_copy,
+    history::{self, Operation},
+    link::System,
+    util::{ask, confirm, hostname, run_command},
+    Args, ProjectContext,
+};
+use anyhow::{bail, Context, Result};
+use log::*;
+use std::path::{Path, PathBuf};
+
+/// Folder in the home folder named after the repository
+fn default_location(repo: &str) -> Result<PathBuf> {
+    let name = repo
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .map(|x| x.trim_end_matches(".git"))
+        .filter(|x| !x.is_empty())
+        .context(format!("Could not get a folder name from {}", repo))?;
+    Ok(PathBuf::from(std::env::var("HOME")?).join(name))
+}
+
+fn is_git_repo(path: &Path) -> bool {
+    path.join(".git").exists() || (path.join("HEAD").is_file() && path.join("objects").is_dir())
+}
+
+/// Clone `repo` into `location`, or copy it when it is a plain local folder
+async fn fetch(repo: &str, location: &Path) -> Result<()> {
+    if location.exists() {
+        if location.join(".links.toml").exists() {
+            info!("Using the project already at {}", location.display());
+            return Ok(());
+        }
+        bail!("{} already exists", location.display());
+    }
+    if let Some(parent) = location.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let local = Path::new(repo);
+    if local.is_dir() && !is_git_repo(local) {
+        recurse_copy(local, location).await?;
+        history::record(Operation::Create {
+            path: location.to_path_buf(),
+        });
+        info!("Copied {} to {}", repo, location.display());
+        return Ok(());
+    }
+    let status = run_command(
+        "git",
+        ["clone".as_ref(), repo.as_ref(), location.as_os_str()],
+    )
+    .status()
+    .await
+    .context("Could not run git")?;
+    if !status.success() {
+        bail!("Cloning {} failed", repo);
+    }
+    history::record(Operation::Create {
+        path: location.to_path_buf(),
+    });
+    Ok(())
+}
+
+/// The system given with `--system`, this machine's hostname when the project has a system of
+/// that name, or the system the user picks
+fn choose_system(ctx: &ProjectContext, yes: bool) -> Result<Option<System>> {
+    if ctx.args.system.is_some() {
+        return Ok(ctx.args.system.clone());
+    }
+    let systems = &ctx.project.systems;
+    if systems.is_empty() {
+        return Ok(ctx.project.default.clone());
+    }
+    if let Some(system) = hostname()
+        .map(|x| x.parse::<System>().unwrap())
+        .filter(|x| systems.contains(x))
+    {
+        info!("Detected system {}", system);
+        return Ok(Some(system));
+    }
+    if yes {
+        return Ok(ctx.project.default.clone());
+    }
+    println!(
+        "Systems: {}",
+        systems
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let default = ctx
+        .project
+        .default
+        .as_ref()
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    let answer = ask(&format!("System for this machine [{}]:", default))?;
+    if answer.is_empty() {
+        return Ok(ctx.project.default.clone());
+    }
+    let system: System = answer.parse().unwrap();
+    if !systems.contains(&system) {
+        warn!("{} is not one of the project's systems", system);
+    }
+    Ok(Some(system))
+}
+
+/// Fetch a project, manage it as the default for a system, and link it after showing the plan
+pub async fn bootstrap(
+    mut args: Args,
+    repo: String,
+    into: Option<PathBuf>,
+    goal: Option<String>,
+    yes: bool,
+) -> Result<()> {
+    let location = match into {
+        Some(x) => x,
+        None => default_location(&repo)?,
+    };
+    fetch(&repo, &location).await?;
+    if !location.join(".links.toml").exists() {
+        bail!("{} has no .links.toml", location.display());
+    }
+    args.project_path = Some(location.canonicalize()?);
+    args.project = None;
+
+    let ctx = args.clone().try_to_context()?;
+    let system = choose_system(&ctx, yes)?;
+    let mut system_config = manage(&ctx, true)?;
+    if let Some(project) = system_config.projects.get_mut(&ctx.project.id) {
+        project.system = system.clone();
+    }
+    system_config.save(&ctx.system_config_path)?;
+    info!(
+        "Managed {} as the default project{}",
+        ctx.project.name,
+        system
+            .as_ref()
+            .map(|x| format!(" for {}", x))
+            .unwrap_or_default()
+    );
+    args.system = system;
+
+    sync(
+        args.clone().try_to_context()?,
+        goal.clone(),
+        false,
+        true,
+        None,
+    )
+    .await?;
+    if !yes && !confirm("Link these files?")? {
+        info!("Nothing linked, run `dots sync` to link later");
+        return Ok(());
+    }
+    sync(args.try_to_context()?, goal, false, false, None).await
+}
@@ -0,0 +1,213 @@
+use crate::{config::get_sys_config, Args};
+use anyhow::{Context, Result};
+use clap::Parser;
+use clap_generate::Shell;
+use itertools::Itertools;
+
+/// What a word on a dots command line names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    Links,
+    Goals,
+    Systems,
+    Projects,
+}
+
+/// Flags every command takes, used to find the project being completed for
+const GLOBAL_FLAGS: &[&str] = &[
+    "-c",
+    "--config-file",
+    "--project-path",
+    "-p",
+    "--project",
+    "-s",
+    "--system",
+];
+
+/// Flags other than the global ones that are followed by a value
+const VALUE_FLAGS: &[&str] = &[
+    "-g",
+    "--goal",
+    "-l",
+    "--link",
+    "-j",
+    "--jobs",
+    "--destination",
+    "--name",
+    "--exclude",
+    "--into",
+    "--layout",
+    "--from",
+];
+
+fn takes_value(flag: &str) -> bool {
+    GLOBAL_FLAGS.contains(&flag) || VALUE_FLAGS.contains(&flag)
+}
+
+/// Words that are neither flags nor their values
+fn positionals(words: &[String]) -> Vec<&str> {
+    let mut output = Vec::new();
+    let mut words = words.iter();
+    while let Some(word) = words.next() {
+        if word.starts_with('-') {
+            if takes_value(word) {
+                words.next();
+            }
+        } else {
+            output.push(word.as_str());
+        }
+    }
+    output
+}
+
+/// What the word after `words` names, `words` being the command line after `dots`
+pub fn completion_kind(words: &[String]) -> Option<Completion> {
+    use Completion::*;
+    let positionals = positionals(words);
+    let command = positionals.first().copied();
+    match (words.last().map(String::as_str), command) {
+        (Some("-s" | "--system"), _) => return Some(Systems),
+        (Some("-p" | "--project"), _) => return Some(Projects),
+        (Some("-l" | "--link"), Some("revert")) => return Some(Links),
+        (Some("-g" | "--goal"), Some("sync" | "revert" | "unlink")) => return Some(Goals),
+        (Some(flag), _) if takes_value(flag) => return None,
+        _ => {}
+    }
+    match positionals.as_slice() {
+        ["diff"] | ["unlink", ..] => Some(Links),
+        ["goals", "add-file"] | ["goals", "add", _, ..] => Some(Goals),
+        ["projects", "remove" | "rename" | "set-system" | "set-default"] => Some(Projects),
+        ["projects", "set-system", _] => Some(Systems),
+        _ => None,
+    }
+}
+
+/// Arguments with only the global flags of `words`, to load the project they point at
+fn global_args(words: &[String]) -> Option<Args> {
+    let mut globals: Vec<(&str, &str)> = Vec::new();
+    //The last flag may be the one being completed, without a value yet
+    for (flag, value) in words.iter().tuple_windows() {
+        if GLOBAL_FLAGS.contains(&flag.as_str()) {
+            globals.push((flag, value));
+        }
+    }
+    //Systems for `projects set-system` come from the project being changed
+    if let ["projects", "set-system", project] = positionals(words).as_slice() {
+        globals.retain(|(flag, _)| !matches!(*flag, "-p" | "--project"));
+        globals.push(("--project", project));
+    }
+    Args::try_parse_from(
+        std::iter::once("dots")
+            .chain(globals.into_iter().flat_map(|(flag, value)| [flag, value]))
+            .chain(std::iter::once("list")),
+    )
+    .ok()
+}
+
+fn candidates(words: &[String], kind: Completion) -> Result<Vec<String>> {
+    let args = global_args(words).context("Invalid arguments")?;
+    Ok(match kind {
+        Completion::Projects => {
+            let (_, system_config) = get_sys_config(args.config_file.as_ref())?;
+            system_config
+                .projects
+                .into_iter()
+                .map(|(key, project)| project.name.unwrap_or(key))
+                .collect()
+        }
+        Completion::Links => args
+            .try_to_context()?
+            .project
+            .links
+            .into_iter()
+            .map(|x| x.name)
+            .collect(),
+        Completion::Goals => args
+            .try_to_context()?
+            .project
+            .goals
+            .into_iter()
+            .flatten()
+            .map(|x| x.0)
+            .collect(),
+        Completion::Systems => {
+            let project = args.try_to_context()?.project;
+            project
+                .systems
+                .into_iter()
+                .chain(project.default)
+                .chain(
+                    project
+                        .links
+                        .into_iter()
+                        .flat_map(|x| x.src.into_iter().filter_map(|x| x.1)),
+                )
+                .map(|x| x.to_string())
+                .collect()
+        }
+    })
+}
+
+/// Print what the word after `words` can be, one per line. Prints nothing when the word is not
+/// a link, goal, system or project, or when there is no project to read them from
+pub fn complete(words: &[String]) {
+    let kind = match completion_kind(words) {
+        Some(x) => x,
+        None => return,
+    };
+    if let Ok(candidates) = candidates(words, kind) {
+        for candidate in candidates.into_iter().sorted().dedup() {
+            println!("{}", candidate);
+        }
+    }
+}
+
+const BASH: &str = r#"
+_dots_dynamic() {
+    local IFS=$'\n'
+    local candidates=($(dots complete -- "${COMP_WORDS[@]:1:COMP_CWORD-1}" 2>/dev/null))
+    if [ ${#candidates[@]} -gt 0 ]; then
+        COMPREPLY=($(compgen -W "${candidates[*]}" -- "${COMP_WORDS[COMP_CWORD]}"))
+    else
+        _dots "$@"
+    fi
+}
+complete -F _dots_dynamic -o bashdefault -o default dots
+"#;
+
+const ZSH: &str = r#"
+_dots_dynamic() {
+    local -a candidates
+    candidates=("${(@f)$(dots complete -- "${(@)words[2,CURRENT-1]}" 2>/dev/null)}")
+    if [[ -n "${candidates[1]}" ]]; then
+        compadd -a candidates
+    else
+        _dots "$@"
+    fi
+}
+compdef _dots_dynamic dots
+"#;
+
+const FISH: &str = r#"
+function __dots_dynamic
+    set -l words (commandline -opc)
+    set -e words[1]
+    dots complete -- $words 2>/dev/null
+end
+function __dots_offers_names
+    set -l names (__dots_dynamic)
+    test (count $names) -gt 0
+end
+complete -c dots -n __dots_offers_names -f -a '(__dots_dynamic)'
+"#;
+
+/// Shell code that asks `dots complete` for links, goals, systems and projects, falling back to
+/// the generated completions for everything else
+pub fn completion_script(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+        _ => "",
+    }
+}
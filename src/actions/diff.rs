@@ -0,0 +1,86 @@
+use super::sync::{resolve_link, LinkState, ResolvedLink};
+use crate::{
+    diff::diff_paths,
+    link::{Link, System},
+    ProjectContext,
+};
+use anyhow::{Context, Result};
+use colored::*;
+
+/// Show how destinations differ from their project sources, or with `systems` how the sources
+/// for two systems differ. A single system is compared with the current system
+pub fn diff(ctx: &ProjectContext, link: Option<String>, systems: &[System]) -> Result<()> {
+    let links: Vec<&Link> = match &link {
+        Some(name) => vec![ctx
+            .project
+            .links
+            .iter()
+            .find(|x| &x.name == name)
+            .context(format!("Could not find link {}", name))?],
+        None => ctx.project.links.iter().collect(),
+    };
+    let variants = match systems {
+        [] => None,
+        [other] => Some((ctx.system.clone(), Some(other.clone()))),
+        [first, second, ..] => Some((Some(first.clone()), Some(second.clone()))),
+    };
+    let mut differences = 0;
+    for link in links {
+        let result = match &variants {
+            Some((first, second)) => diff_variants(ctx, link, first, second),
+            None => diff_destination(ctx, link),
+        };
+        match result {
+            Ok(Some(diff)) => {
+                differences += 1;
+                println!("{}", link.name.yellow().bold());
+                print!("{}", diff);
+            }
+            Ok(None) => {}
+            Err(e) => println!("{}: {} {}", link.name.yellow(), "error".red(), e),
+        }
+    }
+    if differences == 0 {
+        println!("No differences");
+    }
+    Ok(())
+}
+
+fn diff_destination(ctx: &ProjectContext, link: &Link) -> Result<Option<String>> {
+    match resolve_link(ctx, link)? {
+        Some(ResolvedLink {
+            source,
+            destination,
+            state: LinkState::Conflict,
+            ..
+        }) => diff_paths(&source, &destination),
+        Some(ResolvedLink {
+            destination,
+            state: LinkState::Unlinked,
+            ..
+        }) => Ok(Some(format!(
+            "{} is not linked yet\n",
+            destination.display()
+        ))),
+        _ => Ok(None),
+    }
+}
+
+fn diff_variants(
+    ctx: &ProjectContext,
+    link: &Link,
+    first: &Option<System>,
+    second: &Option<System>,
+) -> Result<Option<String>> {
+    let (first, second) = match (link.src.resolve(first), link.src.resolve(second)) {
+        (Some(first), Some(second)) => (first, second),
+        _ => return Ok(None),
+    };
+    if first == second {
+        return Ok(None);
+    }
+    diff_paths(
+        &ctx.project_config_path.join(first),
+        &ctx.project_config_path.join(second),
+    )
+}
@@ -31,24 +31,27 @@ pub async fn goals(
         },
         AddFile { goal, files } => {
             for file in files {
-                anyhow::ensure!(
-                    ctx.in_project(
-                        &crate::config::ProjectConfig::remove_start(
-                            &ctx.project_config_path,
-                            &file
-                        )
-                        .context("does not start with config_path")?
-                    )?,
-                    "File not in project"
-                );
-                project_config
+                let relative =
+                    crate::config::ProjectConfig::remove_start(&ctx.project_config_path, &file)
+                        .context("does not start with config_path")?;
+                anyhow::ensure!(ctx.in_project(&relative)?, "File not in project");
+                //Goals hold link names, so the file is added through the link that has it
+                let link = ctx
+                    .project
+                    .links
+                    .iter()
+                    .find(|x| x.src.contains_path(&relative))
+                    .context(format!("No link has {} as a source", relative))?;
+                let links = &mut project_config
                     .goals
                     .as_mut()
                     .context("No Goals for project".to_string())?
                     .get_mut(&goal)
                     .context(format!("Could not find goal {}", goal))?
-                    .links
-                    .push(file.to_str().unwrap().to_string());
+                    .links;
+                if !links.contains(&link.name) {
+                    links.push(link.name.clone());
+                }
             }
         }
         Add { name, depends } => {
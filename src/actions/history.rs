@@ -0,0 +1,289 @@
+use crate::{
+    backup::BackupStore,
+    config::SystemConfig,
+    diff::unified_diff,
+    file_actions::{recurse_copy, replace_symlink, temp_link},
+    history::{self, Entry, Operation},
+    privileged::{Escalation, PrivilegedOp, PrivilegedPlan},
+    util::confirm,
+};
+use anyhow::{bail, Context, Result};
+use colored::*;
+use log::*;
+use std::{cmp::Reverse, collections::HashSet, fs, path::Path};
+
+/// Ids of the entries an undo has reversed
+fn undone(entries: &[Entry]) -> HashSet<u64> {
+    entries
+        .iter()
+        .flat_map(|x| x.undoes.iter().flatten().copied())
+        .collect()
+}
+
+fn print_entry(entry: &Entry, undone: bool, verbose: bool) {
+    println!(
+        "{} {} {}{}",
+        format!("#{}", entry.id).yellow(),
+        humantime::format_rfc3339_seconds(entry.time()),
+        format!("dots {}", entry.command).bold(),
+        if undone {
+            " (undone)".dimmed()
+        } else {
+            "".normal()
+        }
+    );
+    if let Some(undoes) = &entry.undoes {
+        println!(
+            "    undid {}",
+            undoes
+                .iter()
+                .map(|x| format!("#{}", x))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    for operation in &entry.operations {
+        println!("    {}", operation);
+        if let (true, Operation::Config { before, after, .. }) = (verbose, operation) {
+            let before = before.as_deref().unwrap_or_default();
+            if let Some(diff) = unified_diff(before, after, "before", "after") {
+                print!("{}", diff);
+            }
+        }
+    }
+}
+
+/// Print the newest `limit` entries of the history, newest first
+pub fn history(limit: Option<usize>, verbose: bool) -> Result<()> {
+    let entries = history::entries()?;
+    let undone = undone(&entries);
+    for entry in entries.iter().rev().take(limit.unwrap_or(usize::MAX)) {
+        print_entry(entry, undone.contains(&entry.id), verbose);
+    }
+    Ok(())
+}
+
+/// Back up `path` before undoing changes it, recording the backup in the undo's entry
+fn back_up(store: &BackupStore, path: &Path) -> Result<Option<String>> {
+    let id = store.snapshot(path, "undo")?;
+    if let Some(id) = &id {
+        history::record(Operation::Backup {
+            path: path.to_path_buf(),
+            backup: id.clone(),
+        });
+    }
+    Ok(id)
+}
+
+/// Remove what an operation created, keeping a backup of anything that is not a symlink. Returns
+/// the privileged operation removing it when the user can't
+fn remove(store: &BackupStore, no_sudo: bool, path: &Path) -> Result<Option<PrivilegedOp>> {
+    let metadata = match path.symlink_metadata() {
+        Ok(x) => x,
+        Err(_) => {
+            debug!("{} is already gone", path.display());
+            return Ok(None);
+        }
+    };
+    if !metadata.file_type().is_symlink() {
+        if let Some(id) = back_up(store, path)? {
+            info!("Backed up {} as {}", path.display(), id);
+        }
+    }
+    if Escalation::decide(path, None, no_sudo).check(path)? {
+        return Ok(Some(if metadata.is_dir() {
+            PrivilegedOp::RemoveAll(path.to_path_buf())
+        } else {
+            PrivilegedOp::Remove(path.to_path_buf())
+        }));
+    }
+    if metadata.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    info!("Removed {}", path.display());
+    Ok(None)
+}
+
+/// Reverse `operation`, adding what has to be done through the sudo program to `plan`
+async fn reverse(
+    store: &BackupStore,
+    plan: &mut PrivilegedPlan,
+    no_sudo: bool,
+    operation: &Operation,
+) -> Result<()> {
+    match operation {
+        Operation::Config {
+            path,
+            before,
+            after,
+        } => {
+            let current = fs::read_to_string(path).ok();
+            let changed = current.is_some() && current.as_ref() != Some(after);
+            if changed {
+                if let Some(id) = back_up(store, path)? {
+                    warn!("{} changed since, backed up as {}", path.display(), id);
+                }
+            }
+            match before {
+                Some(text) => {
+                    fs::write(path, text)?;
+                    history::record(Operation::Config {
+                        path: path.clone(),
+                        before: current,
+                        after: text.clone(),
+                    });
+                }
+                None if current.is_some() => {
+                    if !changed {
+                        back_up(store, path)?;
+                    }
+                    fs::remove_file(path)?;
+                }
+                None => {}
+            }
+            info!("Restored {}", path.display());
+        }
+        Operation::Backup { path, backup } => {
+            //Something already put back, like a file moved back into place, is left alone
+            if path.symlink_metadata().is_ok() {
+                debug!("Not restoring {}, {} exists", backup, path.display());
+                return Ok(());
+            }
+            let backup = store
+                .get(backup)
+                .context("The backup may have been removed by gc")?;
+            store.restore(&backup, path)?;
+            history::record(Operation::Create { path: path.clone() });
+            info!("Restored {}", path.display());
+        }
+        Operation::Create { path } => {
+            if let Some(op) = remove(store, no_sudo, path)? {
+                plan.push(operation.to_string(), vec![op]);
+            }
+        }
+        Operation::CreateDir { path } => {
+            if Escalation::decide(path, None, no_sudo).check(path)? {
+                plan.push(
+                    operation.to_string(),
+                    vec![PrivilegedOp::RemoveDir(path.clone())],
+                );
+                return Ok(());
+            }
+            match fs::remove_dir(path) {
+                Ok(_) => info!("Removed folder {}", path.display()),
+                Err(e) => debug!("Not removing {}: {}", path.display(), e),
+            }
+        }
+        Operation::Move { from, to } => {
+            if from.symlink_metadata().is_ok() {
+                bail!("{} exists", from.display());
+            }
+            match fs::rename(to, from) {
+                Ok(_) => {}
+                Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+                    recurse_copy(to, from).await?;
+                    if to.is_dir() {
+                        fs::remove_dir_all(to)?;
+                    } else {
+                        fs::remove_file(to)?;
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+            history::record(Operation::Move {
+                from: to.clone(),
+                to: from.clone(),
+            });
+            info!("Moved {} back to {}", to.display(), from.display());
+        }
+        Operation::Retarget { path, from } => {
+            let is_symlink = path
+                .symlink_metadata()
+                .map(|x| x.file_type().is_symlink())
+                .unwrap_or(false);
+            if !is_symlink {
+                bail!("{} is no longer a symlink", path.display());
+            }
+            if Escalation::decide(path, None, no_sudo).check(path)? {
+                let temp = temp_link(path);
+                plan.push(
+                    operation.to_string(),
+                    vec![
+                        PrivilegedOp::Symlink {
+                            source: from.clone(),
+                            destination: temp.clone(),
+                        },
+                        PrivilegedOp::Rename {
+                            from: temp,
+                            to: path.clone(),
+                        },
+                    ],
+                );
+                return Ok(());
+            }
+            replace_symlink(from, path)?;
+            info!("Pointed {} back at {}", path.display(), from.display());
+        }
+    }
+    Ok(())
+}
+
+/// Reverse the newest `count` entries that have not been undone, newest first. Operations that
+/// fail are reported and skipped. Paths the user can't change are changed through the sudo
+/// program, unless `no_sudo` is set
+pub async fn undo(
+    system_config: &SystemConfig,
+    no_sudo: bool,
+    count: usize,
+    yes: bool,
+) -> Result<()> {
+    let entries = history::entries()?;
+    let undone = undone(&entries);
+    let targets: Vec<&Entry> = entries
+        .iter()
+        .rev()
+        .filter(|x| x.undoes.is_none() && !undone.contains(&x.id))
+        .take(count)
+        .collect();
+    if targets.is_empty() {
+        info!("Nothing to undo");
+        return Ok(());
+    }
+    for entry in &targets {
+        print_entry(entry, false, false);
+    }
+    if !yes && !confirm("Undo these?")? {
+        info!("Nothing undone");
+        return Ok(());
+    }
+    let store = BackupStore::open()?;
+    let sudo_program = system_config.sudo_program.as_deref().unwrap_or("sudo");
+    for entry in &targets {
+        //Folders go last, deepest first, once what was put in them is gone
+        let (mut dirs, operations): (Vec<&Operation>, Vec<&Operation>) = entry
+            .operations
+            .iter()
+            .partition(|x| matches!(x, Operation::CreateDir { .. }));
+        dirs.sort_by_key(|x| match x {
+            Operation::CreateDir { path } => Reverse(path.components().count()),
+            _ => Reverse(0),
+        });
+        //Privileged changes run together, in the same order, once the rest of the entry is undone
+        let mut plan = PrivilegedPlan::new();
+        for operation in operations.into_iter().rev().chain(dirs) {
+            if let Err(e) = reverse(&store, &mut plan, no_sudo, operation).await {
+                error!("Could not undo \"{}\": {:?}", operation, e);
+            }
+        }
+        for (name, res) in plan.run(sudo_program).await? {
+            match res {
+                Ok(_) => info!("Undid \"{}\"", name),
+                Err(e) => error!("Could not undo \"{}\": {}", name, e),
+            }
+        }
+        info!("Undid #{} dots {}", entry.id, entry.command);
+    }
+    history::commit_undo(targets.iter().map(|x| x.id).collect())
+}
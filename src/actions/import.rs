@@ -0,0 +1,393 @@
+use super::scan::{entries, link_name, propose, Layout};
+use crate::{
+    config::ProjectConfig,
+    goals::Goal,
+    ignore::{IgnoreRules, IGNORE_FILE},
+    link::{Link, Perms, SourceFile, System},
+};
+use anyhow::{Context, Result};
+use clap::ArgEnum;
+use colored::*;
+use itertools::Itertools;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+/// Dotfile manager to import a folder from
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportSource {
+    Stow,
+    Chezmoi,
+    Yadm,
+}
+
+/// Paths that could not be translated, with the reason why
+type Report = Vec<(PathBuf, String)>;
+
+fn source(src: &Path) -> SourceFile {
+    SourceFile::Source {
+        system: None,
+        src: src.to_string_lossy().to_string(),
+    }
+}
+
+fn home(destination: &Path) -> String {
+    format!("$HOME/{}", destination.display())
+}
+
+/// Every stow package becomes a goal containing its links
+fn import_stow(root: &Path, home_dir: &Path, project: &mut ProjectConfig) -> Result<Report> {
+    let ignore = IgnoreRules::from_file(&root.join(IGNORE_FILE))?.with_root(root);
+    let mut report = Report::new();
+    let mut goals: HashMap<String, Goal> = HashMap::new();
+    for package in entries(root, Path::new(""), &ignore)? {
+        let local_ignore = package.join(".stow-local-ignore");
+        if root.join(&local_ignore).exists() {
+            report.push((
+                local_ignore,
+                "stow ignore lists are not translated, add the patterns to .dotsignore".into(),
+            ));
+        }
+    }
+    for proposal in propose(root, home_dir, Layout::Stow, &ignore)? {
+        if proposal.src.ends_with(".stow-local-ignore") {
+            continue;
+        }
+        let name = link_name(&project.links, &proposal.destination)?;
+        if let Some(package) = proposal.package {
+            goals
+                .entry(package)
+                .or_insert_with(|| Goal::new(Vec::new()))
+                .links
+                .push(name.clone());
+        }
+        project.links.push(Link::new(
+            name,
+            home(&proposal.destination).into(),
+            source(&proposal.src),
+        ));
+    }
+    if !goals.is_empty() {
+        project.goals = Some(goals);
+    }
+    Ok(report)
+}
+
+/// Attributes chezmoi encodes in a source name
+#[derive(Default)]
+struct ChezmoiName {
+    target: String,
+    private: bool,
+    readonly: bool,
+    executable: bool,
+}
+
+impl ChezmoiName {
+    fn is_plain(&self, source: &str) -> bool {
+        self.target == source && !self.private && !self.readonly && !self.executable
+    }
+
+    fn perms(&self) -> Option<Perms> {
+        let mut modes = Vec::new();
+        if self.private {
+            modes.push("go-rwx");
+        }
+        if self.readonly {
+            modes.push("a-w");
+        }
+        if self.executable {
+            modes.push("u+x");
+        }
+        if modes.is_empty() {
+            return None;
+        }
+        Some(Perms {
+            user_owner: None,
+            group_owner: None,
+            user_code: Some(modes.join(",")),
+        })
+    }
+}
+
+/// Translate a chezmoi source name into the name it is applied as, or why it can't be
+fn chezmoi_name(source: &str) -> Result<ChezmoiName, String> {
+    if source.ends_with(".tmpl") {
+        return Err("templates are not supported".into());
+    }
+    let mut name = ChezmoiName::default();
+    let mut rest = source;
+    while let Some((prefix, next)) = rest.split_once('_') {
+        match prefix {
+            "private" => name.private = true,
+            "readonly" => name.readonly = true,
+            "executable" => name.executable = true,
+            "exact" | "empty" => {}
+            "dot" => {
+                name.target = format!(".{}", next);
+                return Ok(name);
+            }
+            "literal" => {
+                name.target = next.into();
+                return Ok(name);
+            }
+            "encrypted" => return Err("encrypted files are not supported".into()),
+            "run" | "modify" | "create" | "remove" | "symlink" | "external" => {
+                return Err(format!("{}_ entries are not supported", prefix))
+            }
+            _ => break,
+        }
+        rest = next;
+    }
+    name.target = rest.into();
+    Ok(name)
+}
+
+/// Whether every entry below `path` keeps its name when chezmoi applies it
+fn chezmoi_plain(path: &Path) -> Result<bool> {
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let source = entry.file_name().to_string_lossy().to_string();
+        match chezmoi_name(&source) {
+            Ok(name) if name.is_plain(&source) => {}
+            _ => return Ok(false),
+        }
+        if entry.file_type()?.is_dir() && !chezmoi_plain(&entry.path())? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Link a chezmoi folder as a whole when nothing inside it is renamed, otherwise link its entries
+fn import_chezmoi_dir(
+    root: &Path,
+    relative: &Path,
+    destination: &Path,
+    project: &mut ProjectConfig,
+    report: &mut Report,
+) -> Result<()> {
+    let mut children: Vec<_> = std::fs::read_dir(root.join(relative))?
+        .map(|x| Ok(x?.file_name()))
+        .collect::<Result<_>>()?;
+    children.sort();
+    for child in children {
+        let source_name = child.to_string_lossy().to_string();
+        let src = relative.join(&child);
+        if source_name.starts_with('.') {
+            if source_name.starts_with(".chezmoi") && source_name != ".chezmoidata.toml" {
+                report.push((src, "chezmoi configuration is not translated".into()));
+            }
+            continue;
+        }
+        let name = match chezmoi_name(&source_name) {
+            Ok(x) => x,
+            Err(reason) => {
+                report.push((src, reason));
+                continue;
+            }
+        };
+        let target = destination.join(&name.target);
+        let path = root.join(&src);
+        if path.is_dir() && !chezmoi_plain(&path)? {
+            if name.perms().is_some() {
+                report.push((
+                    src.clone(),
+                    "folder attributes are not kept when linking its contents".into(),
+                ));
+            }
+            import_chezmoi_dir(root, &src, &target, project, report)?;
+            continue;
+        }
+        let mut link = Link::new(
+            link_name(&project.links, &target)?,
+            home(&target).into(),
+            source(&src),
+        );
+        link.perms = name.perms();
+        project.links.push(link);
+    }
+    Ok(())
+}
+
+/// Top level values in `.chezmoidata.toml` become project variables
+fn chezmoi_variables(root: &Path, report: &mut Report) -> Result<Option<HashMap<String, String>>> {
+    for other in [".chezmoidata.yaml", ".chezmoidata.json"] {
+        if root.join(other).exists() {
+            report.push((other.into(), "only .chezmoidata.toml is translated".into()));
+        }
+    }
+    let path = root.join(".chezmoidata.toml");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data: toml::value::Table = toml::from_str(&std::fs::read_to_string(&path)?)
+        .context(format!("Could not parse {}", path.display()))?;
+    let mut variables = HashMap::new();
+    for (key, value) in data {
+        let value = match value {
+            toml::Value::String(x) => x,
+            toml::Value::Integer(x) => x.to_string(),
+            toml::Value::Float(x) => x.to_string(),
+            toml::Value::Boolean(x) => x.to_string(),
+            _ => {
+                report.push((
+                    PathBuf::from(format!(".chezmoidata.toml: {}", key)),
+                    "only plain values become variables".into(),
+                ));
+                continue;
+            }
+        };
+        variables.insert(key, value);
+    }
+    Ok(Some(variables))
+}
+
+fn import_chezmoi(root: &Path, project: &mut ProjectConfig) -> Result<Report> {
+    let mut report = Report::new();
+    project.variables = chezmoi_variables(root, &mut report)?;
+    import_chezmoi_dir(root, Path::new(""), Path::new(""), project, &mut report)?;
+    Ok(report)
+}
+
+/// Every file of a yadm checkout, relative to it
+fn yadm_files(root: &Path, relative: &Path, output: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if path == Path::new(".git") {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            yadm_files(root, &path, output)?;
+        } else {
+            output.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The system a yadm alternate suffix applies to, `None` for `default`
+fn yadm_condition(suffix: &str) -> Result<Option<System>, String> {
+    if suffix.contains(',') {
+        return Err("alternates with more than one condition are not supported".into());
+    }
+    if suffix == "default" {
+        return Ok(None);
+    }
+    match suffix.split_once('.') {
+        Some(("hostname" | "h" | "os" | "o", value)) => Ok(Some(value.parse().unwrap())),
+        _ => Err(format!("##{} alternates are not supported", suffix)),
+    }
+}
+
+/// yadm alternates for hosts and operating systems become variants for systems of the same name
+fn import_yadm(root: &Path, project: &mut ProjectConfig) -> Result<Report> {
+    let mut report = Report::new();
+    let mut files = Vec::new();
+    yadm_files(root, Path::new(""), &mut files)?;
+    files.sort();
+    let alt_dir = Path::new(".config/yadm/alt");
+    let mut targets: BTreeMap<PathBuf, Vec<(Option<System>, PathBuf)>> = BTreeMap::new();
+    let mut plain: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+    for src in files {
+        let relative = src.strip_prefix(alt_dir).unwrap_or(&src);
+        if relative.starts_with(".config/yadm") {
+            report.push((src, "yadm configuration is not translated".into()));
+            continue;
+        }
+        let name = relative
+            .file_name()
+            .context("Invalid file name")?
+            .to_string_lossy()
+            .to_string();
+        match name.split_once("##") {
+            Some((base, suffix)) => match yadm_condition(suffix) {
+                Ok(system) => targets
+                    .entry(relative.with_file_name(base))
+                    .or_default()
+                    .push((system, src)),
+                Err(reason) => report.push((src, reason)),
+            },
+            None => {
+                plain.insert(relative.to_path_buf(), src);
+            }
+        }
+    }
+    for (target, src) in plain {
+        if targets.contains_key(&target) {
+            report.push((src, "replaced by its alternates".into()));
+            continue;
+        }
+        project.links.push(Link::new(
+            link_name(&project.links, &target)?,
+            home(&target).into(),
+            source(&src),
+        ));
+    }
+    for (target, variants) in targets {
+        let mut default_path = None;
+        let mut source_map = HashMap::new();
+        for (system, src) in variants {
+            let src = src.to_string_lossy().to_string();
+            match system {
+                Some(system) => {
+                    if !project.systems.contains(&system) {
+                        project.systems.push(system.clone());
+                    }
+                    source_map.insert(system, src);
+                }
+                None => default_path = Some(src),
+            }
+        }
+        let src = if source_map.is_empty() {
+            SourceFile::Source {
+                system: None,
+                src: default_path.context("Alternate without variants")?,
+            }
+        } else {
+            SourceFile::DynamicSource {
+                default_path,
+                default_system: None,
+                source_map,
+            }
+        };
+        project.links.push(Link::new(
+            link_name(&project.links, &target)?,
+            home(&target).into(),
+            src,
+        ));
+    }
+    Ok(report)
+}
+
+/// Translate a folder managed by another dotfile manager into a project linked into `home`,
+/// printing everything that could not be translated
+pub fn import(root: &Path, home: &Path, from: ImportSource) -> Result<ProjectConfig> {
+    let name = root
+        .file_name()
+        .context("Invalid project folder")?
+        .to_string_lossy()
+        .to_string();
+    let mut project = ProjectConfig::new(name, root);
+    let report = match from {
+        ImportSource::Stow => import_stow(root, home, &mut project)?,
+        ImportSource::Chezmoi => import_chezmoi(root, &mut project)?,
+        ImportSource::Yadm => import_yadm(root, &mut project)?,
+    };
+    for link in &project.links {
+        println!(
+            "{}: {} -> {}",
+            link.name.yellow(),
+            link.src.clone().into_iter().map(|x| x.2).join(", "),
+            link.destination.to_string().green()
+        );
+    }
+    if !report.is_empty() {
+        println!("{}", "Could not translate".red().bold());
+        for (path, reason) in &report {
+            println!("{}: {}", path.display(), reason);
+        }
+    }
+    Ok(project)
+}
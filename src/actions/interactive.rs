@@ -0,0 +1,552 @@
+use super::{
+    sync::{link_links, resolve_link, LinkState},
+    unlink,
+};
+use crate::{history, link::Link, Args, ProjectContext};
+use anyhow::{Context, Result};
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    collections::HashSet,
+    io::{Stdout, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    Frame, Terminal,
+};
+
+type Term = Terminal<CrosstermBackend<Stdout>>;
+
+/// How often link states are checked again while nothing happens
+const REFRESH: Duration = Duration::from_secs(1);
+
+const HELP: &str =
+    "tab: switch  space: select/toggle goal  s: sync  u: unlink  e: edit  r: refresh  q: quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Links,
+    Goals,
+}
+
+/// Short description of a link's state and the colour to show it in
+fn link_state(ctx: &ProjectContext, link: &Link) -> (String, Color) {
+    match resolve_link(ctx, link) {
+        Ok(Some(resolved)) => match resolved.state {
+            LinkState::Linked => ("linked".into(), Color::Green),
+            LinkState::Unlinked => ("not linked".into(), Color::Yellow),
+            LinkState::Conflict => ("conflict".into(), Color::Red),
+            LinkState::Dangling => ("dangling".into(), Color::Red),
+        },
+        Ok(None) => ("no source for system".into(), Color::DarkGray),
+        Err(e) => (format!("error: {}", e), Color::Red),
+    }
+}
+
+struct App {
+    args: Args,
+    ctx: ProjectContext,
+    states: Vec<(String, Color)>,
+    goals: Vec<String>,
+    pane: Pane,
+    link_list: ListState,
+    goal_list: ListState,
+    selected: HashSet<String>,
+    message: String,
+}
+
+impl App {
+    fn new(args: Args) -> Result<App> {
+        let ctx = args.clone().try_to_context()?;
+        let mut app = App {
+            args,
+            ctx,
+            states: Vec::new(),
+            goals: Vec::new(),
+            pane: Pane::Links,
+            link_list: ListState::default(),
+            goal_list: ListState::default(),
+            selected: HashSet::new(),
+            message: HELP.into(),
+        };
+        app.refresh();
+        Ok(app)
+    }
+
+    /// Reload the project and check the state of every link again
+    fn refresh(&mut self) {
+        match self.args.clone().try_to_context() {
+            Ok(ctx) => self.ctx = ctx,
+            Err(e) => self.message = format!("Could not reload the project: {}", e),
+        }
+        let ctx = &self.ctx;
+        self.states = ctx
+            .project
+            .links
+            .iter()
+            .map(|x| link_state(ctx, x))
+            .collect();
+        self.goals = ctx
+            .project
+            .goals
+            .iter()
+            .flatten()
+            .map(|x| x.0.clone())
+            .collect();
+        self.goals.sort();
+        let names: HashSet<&String> = ctx.project.links.iter().map(|x| &x.name).collect();
+        self.selected.retain(|x| names.contains(x));
+        clamp(&mut self.link_list, ctx.project.links.len());
+        clamp(&mut self.goal_list, self.goals.len());
+    }
+
+    fn current_link(&self) -> Option<&Link> {
+        self.ctx.project.links.get(self.link_list.selected()?)
+    }
+
+    fn current_goal(&self) -> Option<&String> {
+        self.goals.get(self.goal_list.selected()?)
+    }
+
+    fn move_by(&mut self, offset: isize) {
+        let (state, len) = match self.pane {
+            Pane::Links => (&mut self.link_list, self.ctx.project.links.len()),
+            Pane::Goals => (&mut self.goal_list, self.goals.len()),
+        };
+        if len == 0 {
+            return;
+        }
+        let current = state.selected().unwrap_or(0) as isize;
+        state.select(Some((current + offset).rem_euclid(len as isize) as usize));
+    }
+
+    /// The selected links, the current link when none are selected, or the current goal's links
+    fn targets(&self) -> Result<Vec<Link>> {
+        Ok(match self.pane {
+            Pane::Links if self.selected.is_empty() => {
+                self.current_link().cloned().into_iter().collect()
+            }
+            Pane::Links => self
+                .ctx
+                .project
+                .links
+                .iter()
+                .filter(|x| self.selected.contains(&x.name))
+                .cloned()
+                .collect(),
+            Pane::Goals => match self.current_goal() {
+                Some(name) => self
+                    .ctx
+                    .project
+                    .goals
+                    .as_ref()
+                    .and_then(|x| x.get(name))
+                    .context("Could not find goal")?
+                    .get_links(&self.ctx)?,
+                None => Vec::new(),
+            },
+        })
+    }
+
+    fn toggle(&mut self) -> Result<()> {
+        match self.pane {
+            Pane::Links => {
+                if let Some(name) = self.current_link().map(|x| x.name.clone()) {
+                    if !self.selected.remove(&name) {
+                        self.selected.insert(name);
+                    }
+                }
+            }
+            Pane::Goals => {
+                let name = match self.current_goal() {
+                    Some(x) => x.clone(),
+                    None => return Ok(()),
+                };
+                let mut project = self.ctx.project.clone();
+                let goal = project
+                    .goals
+                    .as_mut()
+                    .and_then(|x| x.get_mut(&name))
+                    .context("Could not find goal")?;
+                goal.enabled = !goal.enabled;
+                self.message = format!(
+                    "{} {}",
+                    if goal.enabled { "Enabled" } else { "Disabled" },
+                    name
+                );
+                project.save(&self.ctx)?;
+                history::commit()?;
+                self.refresh();
+            }
+        }
+        Ok(())
+    }
+
+    /// Source of the current link for this system, or its first source
+    fn current_source(&self) -> Option<PathBuf> {
+        let link = self.current_link()?;
+        let src = link
+            .src
+            .resolve(&self.ctx.system)
+            .or_else(|| link.src.clone().into_iter().next().map(|x| x.2))?;
+        Some(self.ctx.project_config_path.join(src))
+    }
+}
+
+fn clamp(state: &mut ListState, len: usize) {
+    state.select(match state.selected() {
+        _ if len == 0 => None,
+        Some(x) => Some(x.min(len - 1)),
+        None => Some(0),
+    });
+}
+
+fn enter_screen() -> Result<Term> {
+    enable_raw_mode()?;
+    let mut stdout = std::io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    Ok(Terminal::new(CrosstermBackend::new(stdout))?)
+}
+
+fn leave_screen(terminal: &mut Term) -> Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    Ok(())
+}
+
+/// Wait for enter so the output of an action can be read before the screen comes back
+fn wait_for_enter() -> Result<()> {
+    print!("Press enter to return");
+    std::io::stdout().flush()?;
+    std::io::stdin().read_line(&mut String::new())?;
+    Ok(())
+}
+
+fn open_in_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+    let mut words = editor.split_whitespace();
+    let program = words.next().context("EDITOR is empty")?;
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .context(format!("Could not run {}", program))?;
+    if !status.success() {
+        anyhow::bail!("{} exited with {}", program, status);
+    }
+    Ok(())
+}
+
+fn link_details(app: &App) -> Vec<Spans<'static>> {
+    let ctx = &app.ctx;
+    let link = match app.current_link() {
+        Some(x) => x,
+        None => return vec![Spans::from("No links in the project")],
+    };
+    let current = link.src.resolve(&ctx.system);
+    let mut lines = vec![
+        Spans::from(vec![
+            Span::styled(
+                "Destination: ",
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(link.destination.to_string()),
+        ]),
+        Spans::from(vec![
+            Span::styled("System: ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(
+                ctx.system
+                    .as_ref()
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(|| "none".into()),
+            ),
+        ]),
+        Spans::from(Span::styled(
+            "Sources:",
+            Style::default().add_modifier(Modifier::BOLD),
+        )),
+    ];
+    for (is_default, system, src) in link.src.clone() {
+        let mut label = system
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| "any system".into());
+        if is_default {
+            label.push_str(" (default)");
+        }
+        let style = if current.as_ref() == Some(&src) {
+            Style::default().fg(Color::Green)
+        } else {
+            Style::default()
+        };
+        lines.push(Spans::from(Span::styled(
+            format!("  {}: {}", label, src),
+            style,
+        )));
+    }
+    let goals: Vec<&String> = ctx
+        .project
+        .goals
+        .iter()
+        .flatten()
+        .filter(|(_, goal)| goal.links.contains(&link.name))
+        .map(|x| x.0)
+        .collect();
+    if !goals.is_empty() {
+        lines.push(Spans::from(vec![
+            Span::styled("Goals: ", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw(
+                goals
+                    .into_iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+        ]));
+    }
+    lines
+}
+
+fn goal_details(app: &App) -> Vec<Spans<'static>> {
+    let goal = match app
+        .current_goal()
+        .and_then(|name| app.ctx.project.goals.as_ref()?.get(name))
+    {
+        Some(x) => x,
+        None => return vec![Spans::from("No goals in the project")],
+    };
+    let mut lines = vec![Spans::from(format!(
+        "Enabled: {}",
+        if goal.enabled { "yes" } else { "no" }
+    ))];
+    if let Some(required) = &goal.required_goals {
+        lines.push(Spans::from(format!("Requires: {}", required.join(", "))));
+    }
+    lines.push(Spans::from(Span::styled(
+        "Links:",
+        Style::default().add_modifier(Modifier::BOLD),
+    )));
+    lines.extend(goal.links.iter().map(|x| Spans::from(format!("  {}", x))));
+    lines
+}
+
+fn block(title: &str, active: bool) -> Block<'_> {
+    let style = if active {
+        Style::default().fg(Color::Cyan)
+    } else {
+        Style::default()
+    };
+    Block::default()
+        .borders(Borders::ALL)
+        .border_style(style)
+        .title(title)
+}
+
+fn draw<B: Backend>(frame: &mut Frame<B>, app: &mut App) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(1)])
+        .split(frame.size());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(45), Constraint::Percentage(55)])
+        .split(rows[0]);
+    let lists = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(70), Constraint::Percentage(30)])
+        .split(columns[0]);
+    let highlight = Style::default().add_modifier(Modifier::REVERSED);
+
+    let links: Vec<ListItem> = app
+        .ctx
+        .project
+        .links
+        .iter()
+        .zip(&app.states)
+        .map(|(link, (state, color))| {
+            let mark = if app.selected.contains(&link.name) {
+                "[x] "
+            } else {
+                "[ ] "
+            };
+            ListItem::new(Spans::from(vec![
+                Span::raw(mark),
+                Span::styled(link.name.clone(), Style::default().fg(Color::Yellow)),
+                Span::raw(" "),
+                Span::styled(state.clone(), Style::default().fg(*color)),
+            ]))
+        })
+        .collect();
+    let title = format!("{} links", app.ctx.project.name);
+    let mut list = List::new(links).block(block(&title, app.pane == Pane::Links));
+    if app.pane == Pane::Links {
+        list = list.highlight_style(highlight);
+    }
+    frame.render_stateful_widget(list, lists[0], &mut app.link_list);
+
+    let goals: Vec<ListItem> = app
+        .goals
+        .iter()
+        .map(|name| {
+            let enabled = app
+                .ctx
+                .project
+                .goals
+                .as_ref()
+                .and_then(|x| x.get(name))
+                .map(|x| x.enabled)
+                .unwrap_or(false);
+            ListItem::new(Spans::from(vec![
+                Span::raw(if enabled { "[on]  " } else { "[off] " }),
+                Span::raw(name.clone()),
+            ]))
+        })
+        .collect();
+    let mut list = List::new(goals).block(block("Goals", app.pane == Pane::Goals));
+    if app.pane == Pane::Goals {
+        list = list.highlight_style(highlight);
+    }
+    frame.render_stateful_widget(list, lists[1], &mut app.goal_list);
+
+    let (title, details) = match app.pane {
+        Pane::Links => ("Link", link_details(app)),
+        Pane::Goals => ("Goal", goal_details(app)),
+    };
+    frame.render_widget(
+        Paragraph::new(details)
+            .block(block(title, false))
+            .wrap(Wrap { trim: false }),
+        columns[1],
+    );
+    frame.render_widget(Paragraph::new(app.message.clone()), rows[1]);
+}
+
+/// What a key asks the screen to do outside of itself
+enum Request {
+    Quit,
+    Sync,
+    Unlink,
+    Edit,
+}
+
+fn handle_key(app: &mut App, key: KeyEvent) -> Result<Option<Request>> {
+    match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            return Ok(Some(Request::Quit))
+        }
+        KeyCode::Char('q') | KeyCode::Esc => return Ok(Some(Request::Quit)),
+        KeyCode::Tab | KeyCode::BackTab => {
+            app.pane = match app.pane {
+                Pane::Links => Pane::Goals,
+                Pane::Goals => Pane::Links,
+            }
+        }
+        KeyCode::Up | KeyCode::Char('k') => app.move_by(-1),
+        KeyCode::Down | KeyCode::Char('j') => app.move_by(1),
+        KeyCode::Char(' ') => app.toggle()?,
+        KeyCode::Char('s') => return Ok(Some(Request::Sync)),
+        KeyCode::Char('u') => return Ok(Some(Request::Unlink)),
+        KeyCode::Char('e') if app.pane == Pane::Links => return Ok(Some(Request::Edit)),
+        KeyCode::Char('r') => {
+            app.refresh();
+            app.message = HELP.into();
+        }
+        _ => {}
+    }
+    Ok(None)
+}
+
+/// Leave the screen to run an action, since actions log and may ask for a password
+async fn run_request(terminal: &mut Term, app: &mut App, request: Request) -> Result<()> {
+    let links = app.targets()?;
+    if links.is_empty() && !matches!(request, Request::Edit) {
+        app.message = "Nothing to do".into();
+        return Ok(());
+    }
+    leave_screen(terminal)?;
+    let res = match request {
+        Request::Sync => match app.args.clone().try_to_context() {
+            Ok(ctx) => link_links(ctx, links, None).await,
+            Err(e) => Err(e),
+        },
+        Request::Unlink => unlink(&app.ctx, &links).await,
+        Request::Edit => match app.current_source() {
+            Some(path) => open_in_editor(&path),
+            None => Err(anyhow::anyhow!("The link has no source")),
+        },
+        Request::Quit => Ok(()),
+    };
+    //Each action is its own history entry, so it can be undone alone
+    if let Err(e) = history::commit() {
+        println!("Could not write history: {:?}", e);
+    }
+    let wait = match &res {
+        Err(e) => {
+            println!("{:?}", e);
+            true
+        }
+        Ok(_) => !matches!(request, Request::Edit),
+    };
+    if wait {
+        wait_for_enter()?;
+    }
+    *terminal = enter_screen()?;
+    app.selected.clear();
+    app.refresh();
+    app.message = match res {
+        Ok(_) => HELP.into(),
+        Err(e) => e.to_string(),
+    };
+    Ok(())
+}
+
+async fn run(terminal: &mut Term, app: &mut App) -> Result<()> {
+    loop {
+        terminal.draw(|frame| draw(frame, app))?;
+        if !event::poll(REFRESH)? {
+            app.refresh();
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) => key,
+            _ => continue,
+        };
+        let request = match handle_key(app, key) {
+            Ok(x) => x,
+            Err(e) => {
+                app.message = e.to_string();
+                continue;
+            }
+        };
+        match request {
+            Some(Request::Quit) => return Ok(()),
+            Some(request) => {
+                if let Err(e) = run_request(terminal, app, request).await {
+                    app.message = e.to_string();
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// Browse the links and goals of the project, toggling goals and syncing, unlinking or editing
+/// links on the way
+pub async fn interactive(args: Args) -> Result<()> {
+    let mut app = App::new(args)?;
+    let mut terminal = enter_screen()?;
+    let res = run(&mut terminal, &mut app).await;
+    leave_screen(&mut terminal)?;
+    res
+}
@@ -3,22 +3,56 @@ use anyhow::*;
 use log::*;
 
 mod add;
+mod adopt;
+mod backups;
+mod bootstrap;
+pub mod complete;
+mod diff;
 pub mod goal;
+mod history;
+mod import;
+mod interactive;
+mod projects;
 mod prune;
+mod relocate;
+mod repair;
 mod revert;
-mod sync;
+pub mod scan;
+mod settings;
+mod status;
+pub mod sync;
+mod unlink;
+pub mod untracked;
+pub mod watch;
 
 pub use add::add;
+pub use adopt::adopt;
+pub use backups::{backups, BackupsSubCommand};
+pub use bootstrap::bootstrap;
+pub use complete::{complete, completion_script};
+pub use diff::diff;
+pub use history::{history, undo};
+pub use import::{import, ImportSource};
+pub use interactive::interactive;
+pub use projects::{projects, ProjectsSubCommand};
 pub use prune::prune;
-pub use revert::revert;
+pub use relocate::relocate;
+pub use repair::repair;
+pub use revert::{eject, revert, RevertTarget};
+pub use scan::{scan, Layout};
+pub use settings::{project_settings, system_settings, ConfigSubCommand};
+pub use status::status;
 pub use sync::sync;
+pub use unlink::unlink;
+pub use untracked::untracked;
+pub use watch::{watch, ConflictPolicy};
 
 pub fn manage(ctx: &super::ProjectContext, make_default: bool) -> Result<SystemConfig> {
     let mut sysconfig = ctx.system_config.clone();
     if !ctx.project_config_path.exists() {
         bail!("Project path does not exist");
     }
-    sysconfig.add_project(ctx.project.name.clone(), ctx.project_config_path.clone());
+    sysconfig.add_project(&ctx.project, ctx.project_config_path.clone());
     if make_default {
         sysconfig.default = Some(ctx.project_config_path.clone());
         info!("Set as default");
@@ -0,0 +1,107 @@
+use crate::{
+    config::{ProjectConfig, SystemConfig},
+    link::System,
+    util::WritableConfig,
+};
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::*;
+use log::*;
+
+#[derive(Parser, Clone)]
+pub enum ProjectsSubCommand {
+    #[clap(about = "List managed projects")]
+    List,
+    #[clap(about = "Stop managing a project, leaving its files and links alone")]
+    Remove { project: String },
+    #[clap(about = "Rename a project, in the system config and its own config")]
+    Rename { project: String, name: String },
+    #[clap(about = "Set the system a project is linked for on this machine")]
+    SetSystem { project: String, system: System },
+    #[clap(about = "Make a project the default")]
+    SetDefault { project: String },
+}
+
+/// Run a projects command, returning the system config when it changed
+pub fn projects(
+    system_config: &SystemConfig,
+    command: ProjectsSubCommand,
+) -> Result<Option<SystemConfig>> {
+    let mut system_config = system_config.clone();
+    use ProjectsSubCommand::*;
+    match command {
+        List => {
+            let mut projects: Vec<_> = system_config.projects.iter().collect();
+            projects.sort_by_key(|(key, x)| x.name.as_deref().unwrap_or(key));
+            for (key, project) in projects {
+                let mut notes = Vec::new();
+                if system_config.default.as_ref() == Some(&project.path) {
+                    notes.push("default".green());
+                }
+                if !project.path.join(".links.toml").exists() {
+                    notes.push("missing".red());
+                }
+                println!(
+                    "{} {} {} {}{}",
+                    project.name.as_deref().unwrap_or(key).yellow().bold(),
+                    key.dimmed(),
+                    project.path.display(),
+                    project
+                        .system
+                        .as_ref()
+                        .map(|x| x.to_string())
+                        .unwrap_or_else(|| "no system".into()),
+                    notes
+                        .iter()
+                        .map(|x| format!(" ({})", x))
+                        .collect::<String>()
+                );
+            }
+            return Ok(None);
+        }
+        Remove { project } => {
+            let (key, _) = system_config.find_project(&project)?;
+            let removed = system_config
+                .projects
+                .remove(&key)
+                .context("Project is not managed")?;
+            if system_config.default.as_ref() == Some(&removed.path) {
+                system_config.default = None;
+                warn!("Removed the default project, there is no default now");
+            }
+            info!("No longer managing {}", removed.path.display());
+        }
+        Rename { project, name } => {
+            let (key, _) = system_config.find_project(&project)?;
+            let output = system_config
+                .projects
+                .get_mut(&key)
+                .context("Project is not managed")?;
+            let mut project_config =
+                ProjectConfig::read_from_file(&output.path.join(".links.toml"))?;
+            project_config.name = name.clone();
+            project_config.save_to(&output.path)?;
+            output.name = Some(name.clone());
+            //Projects registered by name move to their id
+            if key != project_config.id {
+                if let Some(output) = system_config.projects.remove(&key) {
+                    system_config.projects.insert(project_config.id, output);
+                }
+            }
+            info!("Renamed {} to {}", project, name);
+        }
+        SetSystem { project, system } => {
+            let (key, _) = system_config.find_project(&project)?;
+            if let Some(output) = system_config.projects.get_mut(&key) {
+                output.system = Some(system.clone());
+            }
+            info!("{} now links for {}", project, system);
+        }
+        SetDefault { project } => {
+            let (_, output) = system_config.find_project(&project)?;
+            system_config.default = Some(output.path.clone());
+            info!("{} is now the default project", project);
+        }
+    }
+    Ok(Some(system_config))
+}
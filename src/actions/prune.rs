@@ -1,31 +1,99 @@
+use crate::backup;
 use crate::config::ProjectConfig;
 use crate::link::*;
+use crate::privileged::{Escalation, PrivilegedOp, PrivilegedPlan};
+use crate::state::ProjectState;
+use crate::util::confirm;
 use crate::ProjectContext;
 use anyhow::Result;
+use colored::*;
 use log::*;
 use std::fs::remove_file;
+use std::path::{Path, PathBuf};
 
-pub fn prune(ctx: &ProjectContext) -> Result<ProjectConfig> {
+/// What prune does with the destination of a removed source
+#[derive(Debug)]
+enum Decision {
+    Remove(Escalation),
+    Missing,
+    NotSymlink,
+    Foreign(PathBuf),
+    StillValid(PathBuf),
+}
+
+/// Only symlinks into the project that no longer point at anything are owned by dots
+fn decide(ctx: &ProjectContext, destination: &Path, sudo_required: Option<bool>) -> Decision {
+    match destination.symlink_metadata() {
+        Ok(metadata) if metadata.file_type().is_symlink() => {}
+        Ok(_) => return Decision::NotSymlink,
+        Err(_) => return Decision::Missing,
+    }
+    let target = match std::fs::read_link(destination) {
+        Ok(x) => x,
+        Err(_) => return Decision::NotSymlink,
+    };
+    let target = match destination.parent() {
+        Some(parent) if target.is_relative() => parent.join(&target),
+        _ => target,
+    };
+    let project = ctx
+        .project_config_path
+        .canonicalize()
+        .unwrap_or_else(|_| ctx.project_config_path.clone());
+    if !target.starts_with(&project) && !target.starts_with(&ctx.project_config_path) {
+        Decision::Foreign(target)
+    } else if target.exists() {
+        Decision::StillValid(target)
+    } else {
+        Decision::Remove(ctx.escalation(destination, sudo_required))
+    }
+}
+
+/// Where a source of `link` would have been linked to
+fn destination_for(ctx: &ProjectContext, link: &Link, src: &str) -> Result<PathBuf> {
+    let mut destination = link
+        .destination
+        .to_path_buf(ctx.project.variables.as_ref())?;
+    if destination.is_dir() {
+        if let Some(name) = Path::new(src).file_name() {
+            destination.push(name);
+        }
+    }
+    Ok(destination)
+}
+
+pub async fn prune(ctx: &ProjectContext, dry_run: bool, yes: bool) -> Result<ProjectConfig> {
     let project = &ctx.project;
     let mut new_project = project.clone();
+    //Destinations of every missing source, checked once each
+    let mut candidates: Vec<(String, PathBuf, Option<bool>)> = Vec::new();
+    let mut forgotten = false;
     new_project.links = new_project
         .links
         .into_iter()
         .filter_map(|mut link| {
-            match convert_iter_to_source(link.src.into_iter().filter_map(|x| {
-                ctx.project_config_path.join(&x.2).canonicalize().ok()?;
-                Some(x)
-            })) {
-                None => {
-                    info!("removing link {}", &link.name);
-                    match remove_file(
-                        link.destination
-                            .to_path_buf(ctx.project.variables.as_ref())
-                            .ok()?,
-                    ) {
-                        Ok(_) => debug!("Successfully removed link {}", &link.name),
-                        Err(e) => error!("Failed to remove link {}", e.to_string()),
+            let (present, missing): (Vec<_>, Vec<_>) = link
+                .src
+                .clone()
+                .into_iter()
+                .partition(|x| ctx.project_config_path.join(&x.2).exists());
+            forgotten |= !missing.is_empty();
+            for (_, _, src) in &missing {
+                if !present.is_empty() {
+                    println!("{}: {} source {}", link.name.yellow(), "forget".red(), src);
+                }
+                match destination_for(ctx, &link, src) {
+                    Ok(destination) => {
+                        if !candidates.iter().any(|x| x.1 == destination) {
+                            candidates.push((link.name.clone(), destination, link.sudo_required));
+                        }
                     }
+                    Err(e) => error!("Could not get destination for {}: {}", link.name, e),
+                }
+            }
+            match convert_iter_to_source(present.into_iter()) {
+                None => {
+                    println!("{}: {} link", link.name.yellow(), "forget".red());
                     None
                 }
                 Some(src) => {
@@ -35,6 +103,83 @@ pub fn prune(ctx: &ProjectContext) -> Result<ProjectConfig> {
             }
         })
         .collect();
+
+    let mut removals = Vec::new();
+    for (name, destination, sudo_required) in candidates {
+        let decision = decide(ctx, &destination, sudo_required);
+        let note = match &decision {
+            Decision::Remove(Escalation::NotNeeded) => "remove".red(),
+            Decision::Remove(escalation) => format!("remove ({})", escalation).red(),
+            Decision::Missing => "nothing at".dimmed(),
+            Decision::NotSymlink => "keep, not a symlink".green(),
+            Decision::Foreign(target) => {
+                format!("keep, links outside the project to {}", target.display()).green()
+            }
+            Decision::StillValid(target) => {
+                format!("keep, still links to {}", target.display()).green()
+            }
+        };
+        println!("{}: {} {}", name.yellow(), note, destination.display());
+        if let Decision::Remove(escalation) = decision {
+            removals.push((name, destination, escalation));
+        }
+    }
+
+    if !forgotten {
+        info!("Nothing to prune");
+        return Ok(new_project);
+    }
+    if dry_run {
+        println!("{}", "Dry run, nothing was changed".bold());
+        return Ok(project.clone());
+    }
+    if !yes && !confirm("Prune these links?")? {
+        info!("Nothing was pruned");
+        return Ok(project.clone());
+    }
+
+    let mut state = ProjectState::load(&project.id)?;
+    let mut plan = PrivilegedPlan::new();
+    let mut emptied: Vec<Vec<PathBuf>> = Vec::new();
+    for (name, destination, escalation) in removals {
+        if let Err(e) = backup::snapshot(&destination, "prune") {
+            error!("Not removing {}: {}", name, e);
+            continue;
+        }
+        match escalation.check(&destination) {
+            Ok(true) => {
+                let dirs = state.emptied_parents(&destination)?;
+                let mut ops = vec![PrivilegedOp::Remove(destination)];
+                ops.extend(dirs.iter().cloned().map(PrivilegedOp::RemoveDir));
+                plan.push(name, ops);
+                emptied.push(dirs);
+            }
+            Ok(false) => match remove_file(&destination) {
+                Ok(_) => {
+                    debug!("Successfully removed link {}", &name);
+                    for dir in state.remove_empty_parents(&destination)? {
+                        info!("Removed empty folder {}", dir.display());
+                    }
+                }
+                Err(e) => error!("Failed to remove link {}: {}", name, e),
+            },
+            Err(e) => error!("Failed to remove link {}: {}", name, e),
+        }
+    }
+    for ((name, res), dirs) in plan.run(ctx.sudo_program()).await?.into_iter().zip(emptied) {
+        match res {
+            Ok(_) => {
+                debug!("Successfully removed link {}", name);
+                for dir in &dirs {
+                    info!("Removed empty folder {}", dir.display());
+                }
+                state.forget_dirs(&dirs);
+            }
+            Err(e) => error!("Failed to remove link {}: {}", name, e),
+        }
+    }
+    state.save(&project.id)?;
+
     let new_links_len = new_project.links.len();
     let old_links_len = project.links.len();
     if new_links_len == old_links_len {
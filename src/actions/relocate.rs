@@ -0,0 +1,146 @@
+use super::sync::{resolve_link, LinkState};
+use crate::{
+    config::SystemConfig,
+    file_actions::{recurse_copy, replace_symlink, temp_link},
+    history::{self, Operation},
+    link::LinkStyle,
+    privileged::{PrivilegedOp, PrivilegedPlan},
+    ProjectContext,
+};
+use anyhow::{bail, Context, Result};
+use log::*;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// A symlink at a link destination that points into the project
+struct Deployed {
+    name: String,
+    destination: PathBuf,
+    /// Source of the link inside the project
+    relative_source: PathBuf,
+    style: LinkStyle,
+    escalate: bool,
+}
+
+/// Find every linked destination, before the project moves and they stop resolving
+fn deployed_links(ctx: &ProjectContext, old_path: &Path) -> Result<Vec<Deployed>> {
+    let mut deployed = Vec::new();
+    for link in &ctx.project.links {
+        let resolved = match resolve_link(ctx, link) {
+            Ok(Some(x)) if x.state == LinkState::Linked => x,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("Skipping {}: {}", link.name, e);
+                continue;
+            }
+        };
+        let target = match std::fs::read_link(&resolved.destination) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let relative_source = match resolved.source.strip_prefix(old_path) {
+            Ok(x) => x.to_path_buf(),
+            Err(_) => continue,
+        };
+        deployed.push(Deployed {
+            name: link.name.clone(),
+            escalate: resolved.escalation.check(&resolved.destination)?,
+            destination: resolved.destination,
+            relative_source,
+            style: if target.is_relative() {
+                LinkStyle::Relative
+            } else {
+                LinkStyle::Absolute
+            },
+        });
+    }
+    Ok(deployed)
+}
+
+async fn move_project(old_path: &Path, new_path: &Path) -> Result<()> {
+    match fs::rename(old_path, new_path).await {
+        Ok(_) => {}
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            recurse_copy(old_path, new_path).await?;
+            fs::remove_dir_all(old_path).await?;
+        }
+        Err(e) => return Err(e.into()),
+    }
+    history::record(Operation::Move {
+        from: old_path.to_path_buf(),
+        to: new_path.to_path_buf(),
+    });
+    Ok(())
+}
+
+/// Move the project to `new_path` and point every deployed link into it, returning the system
+/// config with the project's new location
+pub async fn relocate(ctx: &ProjectContext, new_path: &Path) -> Result<SystemConfig> {
+    let old_path = ctx.project_config_path.canonicalize()?;
+    if new_path.symlink_metadata().is_ok() {
+        bail!("{} already exists", new_path.display());
+    }
+    let parent = match new_path.parent() {
+        Some(x) if !x.as_os_str().is_empty() => x,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(parent).await?;
+    let new_path = parent
+        .canonicalize()?
+        .join(new_path.file_name().context("Invalid project path")?);
+    if new_path.starts_with(&old_path) {
+        bail!("Cannot move the project inside itself");
+    }
+    let deployed = deployed_links(ctx, &old_path)?;
+
+    move_project(&old_path, &new_path)
+        .await
+        .context(format!("Failed moving {}", old_path.display()))?;
+    info!("Moved {} to {}", old_path.display(), new_path.display());
+
+    let mut plan = PrivilegedPlan::new();
+    for link in deployed {
+        let target = link
+            .style
+            .target(&new_path.join(&link.relative_source), &link.destination);
+        if link.escalate {
+            let temp = temp_link(&link.destination);
+            plan.push(
+                link.name,
+                vec![
+                    PrivilegedOp::Symlink {
+                        source: target,
+                        destination: temp.clone(),
+                    },
+                    PrivilegedOp::Rename {
+                        from: temp,
+                        to: link.destination,
+                    },
+                ],
+            );
+        } else {
+            match replace_symlink(&target, &link.destination) {
+                Ok(_) => info!("Relinked {}", link.name),
+                Err(e) => error!("Failed relinking {}: {}", link.name, e),
+            }
+        }
+    }
+    for (name, result) in plan.run(ctx.sudo_program()).await? {
+        match result {
+            Ok(_) => info!("Relinked {}", name),
+            Err(e) => error!("Failed relinking {}: {}", name, e),
+        }
+    }
+
+    let moved = |path: &PathBuf| path == &ctx.project_config_path || path == &old_path;
+    let mut system_config = ctx.system_config.clone();
+    for project in system_config.projects.values_mut() {
+        if moved(&project.path) {
+            project.path = new_path.clone();
+        }
+    }
+    if system_config.default.as_ref().map_or(false, moved) {
+        system_config.default = Some(new_path);
+    }
+    Ok(system_config)
+}
@@ -0,0 +1,140 @@
+use super::sync::{resolve_link, LinkState, ResolvedLink};
+use crate::{
+    backup,
+    file_actions::{canonicalize_existing, replace_symlink, temp_link},
+    link::Link,
+    privileged::{PrivilegedOp, PrivilegedPlan},
+    ProjectContext,
+};
+use anyhow::Result;
+use colored::*;
+use log::*;
+use std::path::{Path, PathBuf};
+
+/// Folders of every managed project, symlinks into them are ones dots made
+fn managed_roots(ctx: &ProjectContext) -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = ctx
+        .system_config
+        .projects
+        .values()
+        .map(|x| x.path.clone())
+        .chain(std::iter::once(ctx.project_config_path.clone()))
+        .collect();
+    let canonical: Vec<PathBuf> = roots.iter().map(|x| canonicalize_existing(x)).collect();
+    roots.extend(canonical);
+    roots
+}
+
+/// Where the symlink at `destination` currently points, if it needs retargeting
+fn broken_target(resolved: &ResolvedLink, roots: &[PathBuf]) -> Result<Option<PathBuf>> {
+    let destination = &resolved.destination;
+    let is_symlink = destination
+        .symlink_metadata()
+        .map(|x| x.file_type().is_symlink())
+        .unwrap_or(false);
+    if !is_symlink {
+        return Ok(None);
+    }
+    let target = std::fs::read_link(destination)?;
+    let pointed = canonicalize_existing(
+        &destination
+            .parent()
+            .unwrap_or_else(|| Path::new("/"))
+            .join(&target),
+    );
+    match resolved.state {
+        LinkState::Dangling => Ok(Some(target)),
+        LinkState::Conflict if roots.iter().any(|x| pointed.starts_with(x)) => Ok(Some(target)),
+        _ => Ok(None),
+    }
+}
+
+/// Retarget symlinks at link destinations that are dangling or point elsewhere into a managed
+/// project, so they point at the source for the current system
+pub async fn repair(ctx: &ProjectContext) -> Result<()> {
+    let roots = managed_roots(ctx);
+    let mut plan = PrivilegedPlan::new();
+    let mut repaired = 0;
+    let report = |link: &Link, old: &Path, new: &Path, destination: &Path| {
+        println!(
+            "{}: {} {} -> {}",
+            link.name.yellow(),
+            destination.display(),
+            old.display().to_string().red(),
+            new.display().to_string().green()
+        );
+    };
+    for link in &ctx.project.links {
+        let resolved = match resolve_link(ctx, link) {
+            Ok(Some(x)) => x,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("Could not resolve {}: {}", link.name, e);
+                continue;
+            }
+        };
+        let old_target = match broken_target(&resolved, &roots)? {
+            Some(x) => x,
+            None => {
+                if resolved.state == LinkState::Conflict {
+                    debug!(
+                        "{} is not a symlink into a managed project",
+                        resolved.destination.display()
+                    );
+                }
+                continue;
+            }
+        };
+        let destination = &resolved.destination;
+        let target = ctx
+            .project
+            .link_style(link)
+            .target(&resolved.source, destination);
+        let escalate = match resolved.escalation.check(destination) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("Could not repair {}: {}", link.name, e);
+                continue;
+            }
+        };
+        backup::snapshot(destination, "repair")?;
+        if escalate {
+            let temp = temp_link(destination);
+            plan.push(
+                link.name.clone(),
+                vec![
+                    PrivilegedOp::Symlink {
+                        source: target.clone(),
+                        destination: temp.clone(),
+                    },
+                    PrivilegedOp::Rename {
+                        from: temp,
+                        to: destination.clone(),
+                    },
+                ],
+            );
+            report(link, &old_target, &target, destination);
+            repaired += 1;
+        } else {
+            match replace_symlink(&target, destination) {
+                Ok(_) => {
+                    report(link, &old_target, &target, destination);
+                    repaired += 1;
+                }
+                Err(e) => error!("Failed repairing {}: {}", link.name, e),
+            }
+        }
+    }
+    for (name, result) in plan.run(ctx.sudo_program()).await? {
+        if let Err(e) = result {
+            error!("Failed repairing {}: {}", name, e);
+            repaired -= 1;
+        }
+    }
+    if repaired == 0 {
+        println!("Nothing to repair");
+    } else {
+        info!("Repaired {} links", repaired);
+    }
+    Ok(())
+}
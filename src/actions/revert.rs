@@ -1,52 +1,250 @@
-use crate::{config::*, file_actions::recurse_copy, link::*};
+use crate::{
+    backup,
+    config::*,
+    file_actions::recurse_copy,
+    history::{self, Operation},
+    link::*,
+    privileged::{PrivilegedOp, PrivilegedPlan},
+    ProjectContext,
+};
 use log::*;
 
 use anyhow::*;
-use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+use std::path::PathBuf;
 use tokio::fs;
 
-pub async fn revert(ctx: &crate::ProjectContext, path: &Path) -> Result<ProjectConfig> {
-    debug!("path is {}", path.display());
-    let ac_path = path.canonicalize().context("could not find file")?;
-    let mut new_project = ctx.project.clone();
-    let mut dest_path: Option<PathBuf> = None;
-    let new_links = new_project
-        .links
-        .iter()
-        .filter_map(|link| {
-            let mut new_link = link.clone();
-            let link_dest = link
-                .destination
-                .to_path_buf(new_project.variables.as_ref())
-                .ok();
+/// What to revert back out of the project
+#[derive(Debug, Clone)]
+pub enum RevertTarget {
+    Path(PathBuf),
+    Link(String),
+    Goal(String),
+}
 
-            debug!("link_dest = {:?} {:?}", link_dest, ac_path);
-            new_link.src = convert_iter_to_source(link.src.clone().into_iter().filter(|x| {
-                debug!("src = {}", ctx.project_config_path.join(&x.2).display());
-                if same_file::is_same_file(ctx.project_config_path.join(&x.2), &ac_path)
-                    .unwrap_or(false)
-                {
-                    debug!("found it");
-                    dest_path = link_dest.clone();
-                    false
-                } else {
-                    true
-                }
-            }))?;
-            Some(new_link)
-        })
-        .collect();
-    let dest = dest_path.context("could not find path in links")?;
-    debug!("dest is {}", dest.display());
+pub async fn revert(ctx: &ProjectContext, target: RevertTarget) -> Result<ProjectConfig> {
+    //Pairs of link names and the source path to revert for them
+    let targets: Vec<(String, String)> = match target {
+        RevertTarget::Path(path) => {
+            debug!("path is {}", path.display());
+            let ac_path = path.canonicalize().context("could not find file")?;
+            ctx.project
+                .links
+                .iter()
+                .flat_map(|link| {
+                    link.src
+                        .clone()
+                        .into_iter()
+                        .filter(|x| {
+                            same_file::is_same_file(ctx.project_config_path.join(&x.2), &ac_path)
+                                .unwrap_or(false)
+                        })
+                        .map(move |x| (link.name.clone(), x.2))
+                })
+                .collect()
+        }
+        RevertTarget::Link(name) => {
+            let link = ctx
+                .project
+                .links
+                .iter()
+                .find(|x| x.name == name)
+                .context(format!("Could not find link {}", name))?;
+            let src = link
+                .src
+                .resolve(&ctx.system)
+                .context(format!("{} has no source for the current system", name))?;
+            vec![(name, src)]
+        }
+        RevertTarget::Goal(goal) => ctx
+            .project
+            .goals
+            .as_ref()
+            .context("No goals set for project")?
+            .get(&goal)
+            .context(format!("Could not find goal {}", goal))?
+            .get_links(ctx)?
+            .into_iter()
+            .filter_map(|link| Some((link.name.clone(), link.src.resolve(&ctx.system)?)))
+            .collect(),
+    };
+    if targets.is_empty() {
+        bail!("could not find path in links");
+    }
 
-    fs::remove_file(&dest).await?;
-    if ac_path.is_file() {
-        fs::copy(&ac_path, dest).await?;
-        fs::remove_file(&ac_path).await?;
-    } else {
-        recurse_copy(&ac_path, &dest).await?;
-        fs::remove_dir_all(&ac_path).await?;
-    }
-    new_project.links = new_links;
+    let mut new_project = ctx.project.clone();
+    let mut reverted: HashSet<String> = HashSet::new();
+    let mut failed: HashSet<String> = HashSet::new();
+    let mut done: Vec<(String, String)> = Vec::new();
+    let mut plan = PrivilegedPlan::new();
+    let mut escalated: Vec<(String, String)> = Vec::new();
+    for (name, src) in targets {
+        let link = ctx
+            .project
+            .links
+            .iter()
+            .find(|x| x.name == name)
+            .context(format!("Could not find link {}", name))?;
+        match materialise(ctx, link, &src).await {
+            Result::Ok(None) => done.push((name, src)),
+            Result::Ok(Some(ops)) => {
+                plan.push(name.clone(), ops);
+                escalated.push((name, src));
+            }
+            Err(e) => {
+                error!("Failed reverting {}: {}", name, e);
+                failed.insert(src);
+            }
+        }
+    }
+    for ((_, res), (name, src)) in plan
+        .run(ctx.sudo_program())
+        .await?
+        .into_iter()
+        .zip(escalated)
+    {
+        match res {
+            Result::Ok(_) => done.push((name, src)),
+            Err(e) => {
+                error!("Failed reverting {}: {}", name, e);
+                failed.insert(src);
+            }
+        }
+    }
+    for (name, src) in done {
+        info!("Reverted {}", name);
+        new_project.links = new_project
+            .links
+            .into_iter()
+            .filter_map(|mut x| {
+                if x.name == name {
+                    x.src = x.src.remove_link(&src)?;
+                }
+                Some(x)
+            })
+            .collect();
+        reverted.insert(src);
+    }
+    //Only remove sources that were put back everywhere they were reverted to
+    for src in reverted.difference(&failed) {
+        let path = ctx.project_config_path.join(src);
+        backup::snapshot(&path, "revert")?;
+        if path.is_dir() {
+            fs::remove_dir_all(&path).await?;
+        } else {
+            fs::remove_file(&path).await?;
+        }
+    }
+    if reverted.is_empty() {
+        bail!("Nothing was reverted");
+    }
     Ok(new_project)
 }
+
+/// Copy every link for the current system back into place as real files, and with
+/// `remove_config` delete the project config afterwards
+pub async fn eject(ctx: &ProjectContext, remove_config: bool) -> Result<()> {
+    let mut failures = 0;
+    let mut plan = PrivilegedPlan::new();
+    for link in &ctx.project.links {
+        let src = match link.src.resolve(&ctx.system) {
+            Some(x) => x,
+            None => continue,
+        };
+        match materialise(ctx, link, &src).await {
+            Result::Ok(None) => info!("Ejected {}", link.name),
+            Result::Ok(Some(ops)) => plan.push(link.name.clone(), ops),
+            Err(e) => {
+                error!("Failed ejecting {}: {}", link.name, e);
+                failures += 1;
+            }
+        }
+    }
+    for (name, res) in plan.run(ctx.sudo_program()).await? {
+        match res {
+            Result::Ok(_) => info!("Ejected {}", name),
+            Err(e) => {
+                error!("Failed ejecting {}: {}", name, e);
+                failures += 1;
+            }
+        }
+    }
+    if failures > 0 {
+        bail!("{} links could not be ejected", failures);
+    }
+    if remove_config {
+        let config = ctx.project_config_path.join(".links.toml");
+        backup::snapshot(&config, "eject")?;
+        fs::remove_file(&config).await?;
+        info!("Removed {}", config.display());
+    }
+    Ok(())
+}
+
+/// Replace the link to `src` at the destination of `link` with a copy of it. Returns the
+/// privileged operations that do it instead when the destination needs escalation
+async fn materialise(
+    ctx: &ProjectContext,
+    link: &Link,
+    src: &str,
+) -> Result<Option<Vec<PrivilegedOp>>> {
+    let source = ctx
+        .project_config_path
+        .join(src)
+        .canonicalize()
+        .context(format!("Could not find {}", src))?;
+    let mut destination = link
+        .destination
+        .to_path_buf(ctx.project.variables.as_ref())?;
+    if destination.is_dir() && !same_file::is_same_file(&destination, &source)? {
+        destination.push(
+            source
+                .file_name()
+                .context(format!("Could not get file name for {}", link.name))?,
+        );
+    }
+    debug!("dest is {}", destination.display());
+    let linked = match destination.symlink_metadata() {
+        Result::Ok(metadata) if metadata.file_type().is_symlink() => {
+            if !same_file::is_same_file(&destination, &source).unwrap_or(false) {
+                bail!("{} links somewhere else", destination.display());
+            }
+            true
+        }
+        Result::Ok(_) => bail!(
+            "{} exists and is not a link, not replacing it",
+            destination.display()
+        ),
+        Err(_) => false,
+    };
+    let parent = destination
+        .parent()
+        .context("Could not get parent folder")?
+        .to_path_buf();
+    if linked {
+        backup::snapshot(&destination, "revert")?;
+    }
+
+    if ctx
+        .escalation(&destination, link.sudo_required)
+        .check(&destination)?
+    {
+        let mut ops = Vec::new();
+        if linked {
+            ops.push(PrivilegedOp::Remove(destination.clone()));
+        }
+        ops.push(PrivilegedOp::CreateDir(parent));
+        ops.push(PrivilegedOp::Copy {
+            from: source,
+            to: destination,
+        });
+        return Ok(Some(ops));
+    }
+    if linked {
+        fs::remove_file(&destination).await?;
+    }
+    fs::create_dir_all(&parent).await?;
+    recurse_copy(&source, &destination).await?;
+    history::record(Operation::Create { path: destination });
+    Ok(None)
+}
@@ -0,0 +1,222 @@
+use super::untracked::BUILT_IN;
+use crate::{
+    ignore::{IgnoreRules, IGNORE_FILE},
+    link::{Link, SourceFile},
+    util::confirm,
+};
+use anyhow::{bail, Context, Result};
+use clap::ArgEnum;
+use colored::*;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// How an existing folder of dotfiles is laid out
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// The folder, or a `home` folder inside it, mirrors the home folder
+    Home,
+    /// Every top level folder is a GNU stow package mirroring the home folder
+    Stow,
+    /// Top level files are dotfiles without their leading dot
+    Flat,
+}
+
+/// A link found by scanning, with paths relative to the project and the home folder
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Proposal {
+    pub src: PathBuf,
+    pub destination: PathBuf,
+    /// Stow package the link came from
+    pub package: Option<String>,
+}
+
+/// Entries of `relative` inside `root`, leaving out ignored files
+pub(super) fn entries(root: &Path, relative: &Path, ignore: &IgnoreRules) -> Result<Vec<PathBuf>> {
+    let mut output = Vec::new();
+    for entry in std::fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        if relative.as_os_str().is_empty() && BUILT_IN.iter().any(|x| path == Path::new(x)) {
+            continue;
+        }
+        if ignore.is_ignored(&root.join(&path), entry.file_type()?.is_dir()) {
+            continue;
+        }
+        output.push(path);
+    }
+    output.sort();
+    Ok(output)
+}
+
+/// Whether `path` is a folder rather than a symlink to one
+fn is_real_dir(path: &Path) -> bool {
+    path.symlink_metadata()
+        .map(|x| x.file_type().is_dir())
+        .unwrap_or(false)
+}
+
+/// Replace folders with their contents where a folder already exists in `home`, or where more
+/// than one package links to the same place, the way stow folds them
+fn unfold(
+    root: &Path,
+    home: &Path,
+    mut proposals: Vec<Proposal>,
+    ignore: &IgnoreRules,
+) -> Result<Vec<Proposal>> {
+    loop {
+        let mut counts: HashMap<PathBuf, usize> = HashMap::new();
+        for proposal in &proposals {
+            *counts.entry(proposal.destination.clone()).or_default() += 1;
+        }
+        let split = |proposal: &Proposal| {
+            counts[&proposal.destination] > 1
+                || (is_real_dir(&home.join(&proposal.destination))
+                    && root.join(&proposal.src).is_dir())
+        };
+        if !proposals.iter().any(split) {
+            return Ok(proposals);
+        }
+        let mut next = Vec::new();
+        for proposal in proposals {
+            if !split(&proposal) {
+                next.push(proposal);
+                continue;
+            }
+            if !root.join(&proposal.src).is_dir() {
+                bail!(
+                    "{} is in more than one package",
+                    proposal.destination.display()
+                );
+            }
+            for child in entries(root, &proposal.src, ignore)? {
+                next.push(Proposal {
+                    destination: proposal
+                        .destination
+                        .join(child.file_name().context("Invalid file name")?),
+                    src: child,
+                    package: proposal.package.clone(),
+                });
+            }
+        }
+        proposals = next;
+    }
+}
+
+/// Links for the entries of `root` laid out as `layout`, with folders that already exist in
+/// `home` linked by their contents
+pub(crate) fn propose(
+    root: &Path,
+    home: &Path,
+    layout: Layout,
+    ignore: &IgnoreRules,
+) -> Result<Vec<Proposal>> {
+    Ok(match layout {
+        Layout::Home => {
+            let base = if root.join("home").is_dir() {
+                Path::new("home")
+            } else {
+                Path::new("")
+            };
+            let proposals = entries(root, base, ignore)?
+                .into_iter()
+                .map(|src| Proposal {
+                    destination: src.strip_prefix(base).unwrap_or(&src).to_path_buf(),
+                    src,
+                    package: None,
+                })
+                .collect();
+            unfold(root, home, proposals, ignore)?
+        }
+        Layout::Stow => {
+            let mut proposals = Vec::new();
+            for package in entries(root, Path::new(""), ignore)? {
+                if !root.join(&package).is_dir() {
+                    continue;
+                }
+                for src in entries(root, &package, ignore)? {
+                    //Stow's --dotfiles option spells leading dots as dot-
+                    let name = src.strip_prefix(&package)?.to_string_lossy().to_string();
+                    proposals.push(Proposal {
+                        destination: match name.strip_prefix("dot-") {
+                            Some(rest) => PathBuf::from(format!(".{}", rest)),
+                            None => PathBuf::from(name),
+                        },
+                        src,
+                        package: Some(package.to_string_lossy().to_string()),
+                    });
+                }
+            }
+            unfold(root, home, proposals, ignore)?
+        }
+        Layout::Flat => entries(root, Path::new(""), ignore)?
+            .into_iter()
+            .filter(|x| root.join(x).is_file())
+            .map(|src| {
+                let name = src.to_string_lossy();
+                let destination = if name.starts_with('.') {
+                    src.clone()
+                } else {
+                    PathBuf::from(format!(".{}", name))
+                };
+                Proposal {
+                    src,
+                    destination,
+                    package: None,
+                }
+            })
+            .collect(),
+    })
+}
+
+/// Name a link after the file it links, or its whole destination if that name is taken
+pub(super) fn link_name(links: &[Link], destination: &Path) -> Result<String> {
+    let file_name = destination
+        .file_name()
+        .context("Invalid file name")?
+        .to_string_lossy()
+        .to_string();
+    Ok(if links.iter().any(|x| x.name == file_name) {
+        destination.to_string_lossy().to_string()
+    } else {
+        file_name
+    })
+}
+
+/// Propose a link for every entry of an existing folder of dotfiles, returning them once the
+/// user confirms
+pub fn scan(root: &Path, layout: Layout) -> Result<Option<Vec<Link>>> {
+    let ignore = IgnoreRules::from_file(&root.join(IGNORE_FILE))?.with_root(root);
+    let home = PathBuf::from(std::env::var("HOME")?);
+    let proposals = propose(root, &home, layout, &ignore)?;
+    if proposals.is_empty() {
+        bail!("Found nothing to link in {}", root.display());
+    }
+    let mut links: Vec<Link> = Vec::new();
+    for Proposal {
+        src, destination, ..
+    } in proposals
+    {
+        let name = link_name(&links, &destination)?;
+        let destination = format!("$HOME/{}", destination.display());
+        println!(
+            "{}: {} -> {}",
+            name.yellow(),
+            src.display(),
+            destination.green()
+        );
+        links.push(Link::new(
+            name,
+            destination.into(),
+            SourceFile::Source {
+                system: None,
+                src: src.to_string_lossy().to_string(),
+            },
+        ));
+    }
+    if !confirm(&format!("Write {} links to .links.toml?", links.len()))? {
+        return Ok(None);
+    }
+    Ok(Some(links))
+}
@@ -0,0 +1,369 @@
+use crate::{
+    config::{BackupRetention, ProjectConfig, SystemConfig},
+    link::{LinkStyle, System},
+};
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Clone)]
+pub enum ConfigSubCommand {
+    #[clap(about = "Print a setting")]
+    Get {
+        key: String,
+        #[clap(short, long, about = "Use the system config instead of the project")]
+        global: bool,
+    },
+    #[clap(about = "Change a setting")]
+    Set {
+        key: String,
+        value: String,
+        #[clap(short, long, about = "Use the system config instead of the project")]
+        global: bool,
+    },
+    #[clap(about = "Remove a setting")]
+    Unset {
+        key: String,
+        #[clap(short, long, about = "Use the system config instead of the project")]
+        global: bool,
+    },
+    #[clap(about = "Print every setting that is set")]
+    List {
+        #[clap(short, long, about = "Use the system config instead of the project")]
+        global: bool,
+    },
+}
+
+impl ConfigSubCommand {
+    pub fn global(&self) -> bool {
+        use ConfigSubCommand::*;
+        match self {
+            Get { global, .. } | Set { global, .. } | Unset { global, .. } | List { global } => {
+                *global
+            }
+        }
+    }
+}
+
+/// Settings of a project config that can be changed with `dots config`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ProjectKey {
+    Name,
+    Default,
+    Systems,
+    LinkStyle,
+    Variable(String),
+}
+
+const PROJECT_KEYS: &str = "name, default, systems, link_style, variables.NAME";
+
+impl std::str::FromStr for ProjectKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "name" => ProjectKey::Name,
+            "default" => ProjectKey::Default,
+            "systems" => ProjectKey::Systems,
+            "link_style" => ProjectKey::LinkStyle,
+            _ => match s.strip_prefix("variables.") {
+                Some(name)
+                    if !name.is_empty()
+                        && name.chars().all(|x| x.is_alphanumeric() || x == '_') =>
+                {
+                    ProjectKey::Variable(name.to_string())
+                }
+                Some(name) => bail!("Invalid variable name {}", name),
+                None => bail!(
+                    "Unknown project setting {}, expected one of {}",
+                    s,
+                    PROJECT_KEYS
+                ),
+            },
+        })
+    }
+}
+
+impl std::fmt::Display for ProjectKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectKey::Name => write!(f, "name"),
+            ProjectKey::Default => write!(f, "default"),
+            ProjectKey::Systems => write!(f, "systems"),
+            ProjectKey::LinkStyle => write!(f, "link_style"),
+            ProjectKey::Variable(name) => write!(f, "variables.{}", name),
+        }
+    }
+}
+
+/// Settings of the system config that can be changed with `dots config --global`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SystemKey {
+    Default,
+    SudoProgram,
+    IgnoreFile,
+    BackupsMaxAgeDays,
+    BackupsKeepLast,
+}
+
+const SYSTEM_KEYS: &str =
+    "default, sudo_program, ignore_file, backups.max_age_days, backups.keep_last";
+
+impl std::str::FromStr for SystemKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "default" => SystemKey::Default,
+            "sudo_program" => SystemKey::SudoProgram,
+            "ignore_file" => SystemKey::IgnoreFile,
+            "backups.max_age_days" => SystemKey::BackupsMaxAgeDays,
+            "backups.keep_last" => SystemKey::BackupsKeepLast,
+            _ => bail!(
+                "Unknown system setting {}, expected one of {}",
+                s,
+                SYSTEM_KEYS
+            ),
+        })
+    }
+}
+
+fn parse_link_style(value: &str) -> Result<LinkStyle> {
+    match value {
+        "absolute" => Ok(LinkStyle::Absolute),
+        "relative" => Ok(LinkStyle::Relative),
+        _ => bail!(
+            "Invalid link style {}, expected absolute or relative",
+            value
+        ),
+    }
+}
+
+fn link_style_name(style: LinkStyle) -> &'static str {
+    match style {
+        LinkStyle::Absolute => "absolute",
+        LinkStyle::Relative => "relative",
+    }
+}
+
+fn project_get(project: &ProjectConfig, key: &ProjectKey) -> Option<String> {
+    match key {
+        ProjectKey::Name => Some(project.name.clone()),
+        ProjectKey::Default => project.default.as_ref().map(ToString::to_string),
+        ProjectKey::Systems => Some(
+            project
+                .systems
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+        .filter(|x| !x.is_empty()),
+        ProjectKey::LinkStyle => project.link_style.map(|x| link_style_name(x).to_string()),
+        ProjectKey::Variable(name) => project.variables.as_ref()?.get(name).cloned(),
+    }
+}
+
+fn project_set(project: &mut ProjectConfig, key: &ProjectKey, value: Option<&str>) -> Result<()> {
+    match (key, value) {
+        (ProjectKey::Name, Some(value)) => {
+            if value.trim().is_empty() {
+                bail!("The project name cannot be empty");
+            }
+            project.name = value.to_string();
+        }
+        (ProjectKey::Name, None) => bail!("The project name cannot be unset"),
+        (ProjectKey::Default, Some(value)) => {
+            let system: System = value.parse().unwrap();
+            //A project that lists no systems takes any system
+            if !project.systems.is_empty() && !project.systems.contains(&system) {
+                bail!(
+                    "{} is not one of the project's systems, add it to systems first",
+                    system
+                );
+            }
+            project.default = Some(system);
+        }
+        (ProjectKey::Default, None) => project.default = None,
+        (ProjectKey::Systems, value) => {
+            let systems: Vec<System> = value
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(|x| x.parse().unwrap())
+                .collect();
+            if let Some(default) = project
+                .default
+                .as_ref()
+                .filter(|x| !systems.is_empty() && !systems.contains(x))
+            {
+                bail!(
+                    "{} is the default system, change the default first",
+                    default
+                );
+            }
+            project.systems = systems;
+        }
+        (ProjectKey::LinkStyle, value) => {
+            project.link_style = value.map(parse_link_style).transpose()?;
+        }
+        (ProjectKey::Variable(name), Some(value)) => {
+            project
+                .variables
+                .get_or_insert_with(Default::default)
+                .insert(name.clone(), value.to_string());
+        }
+        (ProjectKey::Variable(name), None) => {
+            if let Some(variables) = project.variables.as_mut() {
+                variables.remove(name);
+                if variables.is_empty() {
+                    project.variables = None;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn system_get(system: &SystemConfig, key: SystemKey) -> Option<String> {
+    let backups = system.backups.as_ref();
+    match key {
+        SystemKey::Default => system.default.as_ref().map(|x| x.display().to_string()),
+        SystemKey::SudoProgram => system.sudo_program.clone(),
+        SystemKey::IgnoreFile => system.ignore_file.as_ref().map(|x| x.display().to_string()),
+        SystemKey::BackupsMaxAgeDays => backups?.max_age_days.map(|x| x.to_string()),
+        SystemKey::BackupsKeepLast => backups?.keep_last.map(|x| x.to_string()),
+    }
+}
+
+/// Whether `program` is a path to a file or a file in `PATH`
+fn program_exists(program: &str) -> bool {
+    if program.contains('/') {
+        return Path::new(program).is_file();
+    }
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|x| x.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+fn system_set(system: &mut SystemConfig, key: SystemKey, value: Option<&str>) -> Result<()> {
+    match (key, value) {
+        (SystemKey::Default, Some(value)) => {
+            let path = PathBuf::from(value)
+                .canonicalize()
+                .context(format!("{} does not exist", value))?;
+            if !path.join(".links.toml").exists() {
+                bail!("{} is not a project", path.display());
+            }
+            system.default = Some(path);
+        }
+        (SystemKey::Default, None) => system.default = None,
+        (SystemKey::SudoProgram, Some(value)) => {
+            if !program_exists(value) {
+                bail!("Could not find the program {}", value);
+            }
+            system.sudo_program = Some(value.to_string());
+        }
+        (SystemKey::SudoProgram, None) => system.sudo_program = None,
+        (SystemKey::IgnoreFile, Some(value)) => {
+            let path = PathBuf::from(value)
+                .canonicalize()
+                .context(format!("{} does not exist", value))?;
+            system.ignore_file = Some(path);
+        }
+        (SystemKey::IgnoreFile, None) => system.ignore_file = None,
+        (SystemKey::BackupsMaxAgeDays, value) => {
+            system
+                .backups
+                .get_or_insert_with(BackupRetention::default)
+                .max_age_days = value
+                .map(|x| x.parse().context("Expected a number of days"))
+                .transpose()?;
+        }
+        (SystemKey::BackupsKeepLast, value) => {
+            system
+                .backups
+                .get_or_insert_with(BackupRetention::default)
+                .keep_last = value
+                .map(|x| x.parse().context("Expected a number of backups"))
+                .transpose()?;
+        }
+    }
+    if let Some(BackupRetention {
+        max_age_days: None,
+        keep_last: None,
+    }) = system.backups
+    {
+        system.backups = None;
+    }
+    Ok(())
+}
+
+fn print_setting(key: &str, value: Option<String>) -> Result<()> {
+    println!("{}", value.context(format!("{} is not set", key))?);
+    Ok(())
+}
+
+/// Run a config command on a project, returning the project when it changed
+pub fn project_settings(
+    project: &ProjectConfig,
+    command: ConfigSubCommand,
+) -> Result<Option<ProjectConfig>> {
+    let mut project = project.clone();
+    use ConfigSubCommand::*;
+    match command {
+        Get { key, .. } => {
+            return print_setting(&key, project_get(&project, &key.parse()?)).map(|_| None)
+        }
+        Set { key, value, .. } => project_set(&mut project, &key.parse()?, Some(&value))?,
+        Unset { key, .. } => project_set(&mut project, &key.parse()?, None)?,
+        List { .. } => {
+            let mut keys = vec![
+                ProjectKey::Name,
+                ProjectKey::Default,
+                ProjectKey::Systems,
+                ProjectKey::LinkStyle,
+            ];
+            let mut variables: Vec<_> = project.variables.iter().flatten().map(|x| x.0).collect();
+            variables.sort();
+            keys.extend(
+                variables
+                    .into_iter()
+                    .map(|x| ProjectKey::Variable(x.clone())),
+            );
+            for key in keys {
+                if let Some(value) = project_get(&project, &key) {
+                    println!("{} = {}", key, value);
+                }
+            }
+            return Ok(None);
+        }
+    }
+    Ok(Some(project))
+}
+
+/// Run a config command on the system config, returning it when it changed
+pub fn system_settings(
+    system: &SystemConfig,
+    command: ConfigSubCommand,
+) -> Result<Option<SystemConfig>> {
+    let mut system = system.clone();
+    use ConfigSubCommand::*;
+    match command {
+        Get { key, .. } => {
+            return print_setting(&key, system_get(&system, key.parse()?)).map(|_| None)
+        }
+        Set { key, value, .. } => system_set(&mut system, key.parse()?, Some(&value))?,
+        Unset { key, .. } => system_set(&mut system, key.parse()?, None)?,
+        List { .. } => {
+            for key in SYSTEM_KEYS.split(", ") {
+                if let Some(value) = system_get(&system, key.parse()?) {
+                    println!("{} = {}", key, value);
+                }
+            }
+            return Ok(None);
+        }
+    }
+    Ok(Some(system))
+}
@@ -0,0 +1,92 @@
+use super::sync::{resolve_link, LinkState, ResolvedLink};
+use crate::{link::Link, privileged::Escalation, ProjectContext};
+use anyhow::Result;
+use colored::*;
+
+fn escalation_note(escalation: Escalation) -> ColoredString {
+    match escalation {
+        Escalation::NotNeeded => "".normal(),
+        Escalation::Required => format!(" ({})", escalation).yellow(),
+        Escalation::Refused => format!(" ({})", escalation).red(),
+    }
+}
+
+/// Print the current state of every link in the project
+pub fn status(ctx: &ProjectContext) -> Result<()> {
+    println!("{} {}", "Status for".bold(), ctx.project.name.bold());
+    for link in &ctx.project.links {
+        match resolve_link(ctx, link) {
+            Ok(Some(ResolvedLink {
+                destination,
+                state,
+                escalation,
+                ..
+            })) => {
+                let state = match state {
+                    LinkState::Linked => "linked".green(),
+                    LinkState::Unlinked => "not linked".yellow(),
+                    LinkState::Conflict => "conflict".red(),
+                    LinkState::Dangling => "dangling".red(),
+                };
+                println!(
+                    "{}: {} {}{}",
+                    link.name.yellow(),
+                    state,
+                    destination.display(),
+                    escalation_note(escalation)
+                );
+            }
+            Ok(None) => println!(
+                "{}: {}",
+                link.name.yellow(),
+                "no source for system".dimmed()
+            ),
+            Err(e) => println!("{}: {} {}", link.name.yellow(), "error".red(), e),
+        }
+    }
+    Ok(())
+}
+
+/// Print what syncing `links` would do, without changing anything
+pub fn print_plan(ctx: &ProjectContext, links: &[Link]) {
+    println!("{}", "Dry run, nothing will be changed".bold());
+    for link in links {
+        match resolve_link(ctx, link) {
+            Ok(Some(ResolvedLink {
+                source,
+                destination,
+                state,
+                escalation,
+            })) => match state {
+                LinkState::Linked => {
+                    println!("{}: already linked", link.name.yellow())
+                }
+                LinkState::Conflict => println!(
+                    "{}: {} {} already exists",
+                    link.name.yellow(),
+                    "skip".red(),
+                    destination.display()
+                ),
+                LinkState::Dangling => println!(
+                    "{}: {} {} is dangling, run `dots repair`",
+                    link.name.yellow(),
+                    "skip".red(),
+                    destination.display()
+                ),
+                LinkState::Unlinked => println!(
+                    "{}: link {} -> {}{}",
+                    link.name.yellow(),
+                    destination.display(),
+                    source.display(),
+                    escalation_note(escalation)
+                ),
+            },
+            Ok(None) => println!(
+                "{}: {}",
+                link.name.yellow(),
+                "no source for system".dimmed()
+            ),
+            Err(e) => println!("{}: {} {}", link.name.yellow(), "error".red(), e),
+        }
+    }
+}
@@ -1,14 +1,26 @@
-use crate::{link::Link, ProjectContext};
-use anyhow::{Context, Result};
+use crate::{
+    file_actions::{canonicalize_existing, missing_dirs},
+    history::{self, Operation},
+    link::Link,
+    privileged::{Escalation, PrivilegedOp, PrivilegedPlan},
+    state::ProjectState,
+    ProjectContext,
+};
+use anyhow::{bail, Context, Result};
 use futures::TryStreamExt;
+use itertools::Itertools;
 use log::*;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::fs;
+use tokio::{fs, sync::Semaphore};
 
 pub async fn sync(
     ctx: ProjectContext,
     goal: Option<String>,
     installed_programs: bool,
+    dry_run: bool,
+    jobs: Option<usize>,
 ) -> Result<()> {
     let links = match goal {
         Some(goal) => {
@@ -61,149 +73,440 @@ pub async fn sync(
         }
     };
 
-    link_links(ctx, links).await
+    if dry_run {
+        super::status::print_plan(&ctx, &links);
+        return Ok(());
+    }
+    link_links(ctx, links, jobs).await
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    Linked,
+    Unlinked,
+    Conflict,
+    /// A symlink to something that no longer exists
+    Dangling,
+}
+
+/// A link with its source and destination realised for the current system
+#[derive(Debug, Clone)]
+pub struct ResolvedLink {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub state: LinkState,
+    pub escalation: Escalation,
+}
+
+/// Whether a link goes inside the folder at `destination` rather than at it. A symlink into the
+/// project is a link to a folder, even when it points at the wrong one
+fn links_inside(ctx: &ProjectContext, destination: &Path) -> bool {
+    if !destination.is_dir() {
+        return false;
+    }
+    let is_symlink = destination
+        .symlink_metadata()
+        .map(|x| x.file_type().is_symlink())
+        .unwrap_or(false);
+    !is_symlink
+        || !canonicalize_existing(destination)
+            .starts_with(canonicalize_existing(&ctx.project_config_path))
+}
+
+/// Resolve the source and destination of a link, returns `None` if the link has no source for the
+/// current system
+pub fn resolve_link(ctx: &ProjectContext, link: &Link) -> Result<Option<ResolvedLink>> {
+    let source = match link.src.resolve(&ctx.system) {
+        Some(d) => ctx.project_config_path.join(d),
+        None => return Ok(None),
+    }
+    .canonicalize()?;
+
+    //Parse in environment variables
+    let mut destination = link
+        .destination
+        .to_path_buf(ctx.project.variables.as_ref())?;
+    if links_inside(ctx, &destination) && !same_file::is_same_file(&destination, &source)? {
+        destination.push(
+            source
+                .file_name()
+                .context(format!("Could not get file name for {}", link.name))?,
+        );
+    }
+    //If the destination exists, and links back to the original location, then already linked
+    let state = if destination.exists() && same_file::is_same_file(&destination, &source)? {
+        LinkState::Linked
+    } else if destination.exists() {
+        LinkState::Conflict
+    } else if destination.symlink_metadata().is_ok() {
+        LinkState::Dangling
+    } else {
+        LinkState::Unlinked
+    };
+    let escalation = ctx.escalation(&destination, link.sudo_required);
+    Ok(Some(ResolvedLink {
+        source,
+        destination,
+        state,
+        escalation,
+    }))
+}
+
+/// Outcome of linking a single link
+#[derive(Debug, Default)]
+struct LinkOutcome {
+    /// Privileged operations still required to link it
+    ops: Option<Vec<PrivilegedOp>>,
+    /// Folders created, or to be created by the privileged operations, for the link
+    created: Vec<PathBuf>,
+}
+
+type LinkTask = tokio::task::JoinHandle<Result<LinkOutcome>>;
+
+/// A link to sync, resolved once before anything is linked
+#[derive(Debug)]
+pub struct PendingLink {
+    pub link: Link,
+    /// Path the link is linked at, which is inside the destination when that is a folder
+    pub destination: PathBuf,
+    /// `None` when the link has no source for the current system
+    pub resolved: Result<Option<ResolvedLink>>,
+}
+
+/// Resolve every link for syncing. Links that can't be resolved keep their configured
+/// destination for ordering, and fail when they are linked
+pub fn resolve_links(ctx: &ProjectContext, links: Vec<Link>) -> Result<Vec<PendingLink>> {
+    links
+        .into_iter()
+        .map(|link| {
+            let resolved = resolve_link(ctx, &link);
+            let destination = match &resolved {
+                Ok(Some(resolved)) => resolved.destination.clone(),
+                _ => link
+                    .destination
+                    .to_path_buf(ctx.project.variables.as_ref())
+                    .context(format!("Could not get destination for {}", link.name))?,
+            };
+            Ok(PendingLink {
+                link,
+                destination,
+                resolved,
+            })
+        })
+        .collect()
 }
 
-pub async fn link_links(ctx: ProjectContext, links: Vec<Link>) -> Result<()> {
+pub async fn link_links(ctx: ProjectContext, links: Vec<Link>, jobs: Option<usize>) -> Result<()> {
+    let known: HashSet<&str> = ctx.project.links.iter().map(|x| x.name.as_str()).collect();
+    for link in &links {
+        for dependency in link.after.iter().flatten() {
+            if !known.contains(dependency.as_str()) {
+                bail!("Link {} depends on unknown link {}", link.name, dependency);
+            }
+        }
+    }
     let ctx = Arc::new(ctx);
-    let threads = links.into_iter().map(
-        |link| -> tokio::task::JoinHandle<Result<(), anyhow::Error>> {
-            let ctx = ctx.clone();
-            //Create async threads to link
-            tokio::spawn(async move {
-                async {
-                    let project_path = &ctx.project_config_path;
-                    let source = match link.src.resolve(&ctx.system) {
-                        Some(d) => project_path.join(d),
-                        None => return Ok(()),
+    let pending_links = {
+        let ctx = ctx.clone();
+        tokio::task::spawn_blocking(move || resolve_links(&ctx, links)).await??
+    };
+    let LinkOrder {
+        levels,
+        dependencies,
+    } = order_links(pending_links)?;
+
+    let jobs = jobs
+        .or_else(|| std::thread::available_parallelism().ok().map(|x| x.get()))
+        .unwrap_or(1)
+        .max(1);
+    let semaphore = Arc::new(Semaphore::new(jobs));
+    let mut plan = PrivilegedPlan::new();
+    let mut pending: HashSet<String> = HashSet::new();
+    let mut failed: HashSet<String> = HashSet::new();
+    //Folders created for each link, and whether the privileged plan records them in the history
+    let mut created: Vec<(String, Vec<PathBuf>, bool)> = Vec::new();
+
+    for level in levels {
+        //Links depending on privileged work have to wait for the plan to run
+        if level.iter().any(|x| {
+            dependencies[&x.link.name]
+                .iter()
+                .any(|y| pending.contains(y))
+        }) {
+            run_plan(&ctx, std::mem::take(&mut plan), &mut failed).await?;
+            pending.clear();
+        }
+        let threads: Vec<(String, LinkTask)> = level
+            .into_iter()
+            .filter(|x| {
+                match dependencies[&x.link.name]
+                    .iter()
+                    .find(|y| failed.contains(*y))
+                {
+                    Some(dependency) => {
+                        error!("Skipping {} as {} failed to link", x.link.name, dependency);
+                        false
                     }
-                    .canonicalize()?;
-
-                    //Normalise destination
-                    let destination =
-                        {
-                            //Parse in environment variables
-                            let mut temp_dest = link
-                                .destination
-                                .to_path_buf(ctx.project.variables.as_ref())?;
-                            if temp_dest.is_dir()
-                                && temp_dest.exists()
-                                && !same_file::is_same_file(&temp_dest, &source)?
-                            {
-                                temp_dest.push(source.file_name().context(format!(
-                                    "Could not get file name for {}",
-                                    link.name
-                                ))?);
-                            }
-                            //If the destination exists, and links back to the original location, then already
-                            //linked
-                            if temp_dest.exists() && same_file::is_same_file(&temp_dest, &source)? {
-                                info!(r#""{}" already linked"#, source.display());
-                                return Ok(());
-                            } else if temp_dest.exists() {
-                                error!("{} file already exists", temp_dest.display());
-                                return Ok(());
-                            }
-                            temp_dest
-                        };
-                    // If sudo is required to pass then set perms
-                    if link.sudo_required.unwrap_or(false) {
-                        let sudo_program =
-                            ctx.system_config.sudo_program.as_deref().unwrap_or("sudo");
-                        com_run(
-                            sudo_program,
-                            &[
-                                "mkdir",
-                                "-p",
-                                destination
-                                    .parent()
-                                    .and_then(|x| x.to_str())
-                                    .context("Could not get parent folder")?,
-                            ],
-                        )
-                        .await?;
-                        let dest_str = destination
-                            .to_str()
-                            .context("Could not convert destination to string")?;
-                        com_run(
-                            sudo_program,
-                            &[
-                                "ln",
-                                "-s",
-                                source
-                                    .to_str()
-                                    .context("Could not convert source to string")?,
-                                dest_str,
-                            ],
-                        )
-                        .await?;
-                        if let Some(perms) = link.perms {
-                            if perms.user_owner.is_some() || perms.group_owner.is_some() {
-                                let owner_loc = format!(
-                                    "{}:{}",
-                                    perms.user_owner.unwrap_or_else(|| "".to_string()),
-                                    perms.group_owner.unwrap_or_else(|| "".to_string())
-                                );
-                                com_run(sudo_program, &["chown", "-h", "-R", &owner_loc, dest_str])
-                                    .await?;
-                            }
-                            if let Some(user_code) = perms.user_code {
-                                com_run(sudo_program, &["chmod", "-R", &user_code, dest_str])
-                                    .await?;
-                            }
-                            let source_parent = source
-                                .parent()
-                                .and_then(|x| x.to_str())
-                                .context("Could not get destination parent")?;
-                            log::debug!("dest_parent: {}", source_parent);
-                            com_run(sudo_program, &["chmod", "o+rx", source_parent]).await?;
-                        }
-                    } else {
-                        fs::create_dir_all(
-                            &destination
-                                .parent()
-                                .context("Could not get parent folder")?,
-                        )
+                    None => true,
+                }
+            })
+            .map(|pending_link| {
+                let ctx = ctx.clone();
+                let semaphore = semaphore.clone();
+                let name = pending_link.link.name.clone();
+                //Create async threads to link
+                let task = tokio::spawn(async move {
+                    let _permit = semaphore.acquire_owned().await?;
+                    let name = pending_link.link.name.clone();
+                    link_one(ctx, pending_link)
                         .await
-                        .context(format!(
-                            "Failed creating folder hierchy for {}",
-                            &destination.display()
-                        ))?;
-
-                        fs::symlink(source, &destination).await?;
-                        if let Some(perms) = link.perms {
-                            let dest_str = destination
-                                .to_str()
-                                .context("Could not convert destination to string")?;
-                            if let Some(user_code) = perms.user_code {
-                                com_run("chmod", &["-R", &user_code, dest_str]).await?;
-                            }
-                            if perms.user_owner.is_some() || perms.group_owner.is_some() {
-                                let owner_str = format!(
-                                    "{}:{}",
-                                    perms.user_owner.unwrap_or_default(),
-                                    perms.group_owner.unwrap_or_default()
-                                );
-                                com_run("chown", &["-R", &owner_str, dest_str]).await?;
-                            }
-                        }
+                        .context(format!("Failed linking {}", name))
+                });
+                (name, task)
+            })
+            .collect();
+
+        for (name, res) in threads {
+            match res.await.map_err(Into::into).flatten() {
+                Ok(LinkOutcome { ops, created: dirs }) => {
+                    created.push((name.clone(), dirs, ops.is_some()));
+                    if let Some(ops) = ops {
+                        plan.push(name.clone(), ops);
+                        pending.insert(name);
                     }
-                    Ok::<_, anyhow::Error>(())
                 }
-                .await
-                .context(format!("Failed linking {}", &link.name))
-            })
-        },
-    );
+                Err(e) => {
+                    log::error!("Error syncing : {}", e);
+                    failed.insert(name);
+                }
+            }
+        }
+    }
+    run_plan(&ctx, plan, &mut failed).await?;
+    record_created(&ctx, created, &failed);
+    Ok(())
+}
 
-    for res in threads {
-        if let Err(e) = res.await.map_err(Into::into).flatten() {
-            log::error!("Error syncing : {}", e)
+/// Remember the folders created for links that were linked, so prune can remove them again
+fn record_created(
+    ctx: &ProjectContext,
+    created: Vec<(String, Vec<PathBuf>, bool)>,
+    failed: &HashSet<String>,
+) {
+    let mut dirs = Vec::new();
+    for (_, created, privileged) in created
+        .into_iter()
+        .filter(|(name, ..)| !failed.contains(name))
+    {
+        if !privileged {
+            for dir in &created {
+                history::record(Operation::CreateDir { path: dir.clone() });
+            }
+        }
+        dirs.extend(created);
+    }
+    if dirs.is_empty() {
+        return;
+    }
+    let res = ProjectState::load(&ctx.project.id).and_then(|mut state| {
+        state.record_dirs(dirs);
+        state.save(&ctx.project.id)
+    });
+    if let Err(e) = res {
+        warn!("Could not record created folders: {}", e);
+    }
+}
+
+async fn run_plan(
+    ctx: &ProjectContext,
+    plan: PrivilegedPlan,
+    failed: &mut HashSet<String>,
+) -> Result<()> {
+    for (name, res) in plan.run(ctx.sudo_program()).await? {
+        if let Err(e) = res {
+            log::error!("Error syncing : Failed linking {}: {}", name, e);
+            failed.insert(name);
         }
     }
     Ok(())
 }
 
+/// Link a single link, returning the privileged operations still required to link it
+async fn link_one(ctx: Arc<ProjectContext>, pending: PendingLink) -> Result<LinkOutcome> {
+    let PendingLink { link, resolved, .. } = pending;
+    let resolved = match resolved? {
+        Some(x) => x,
+        None => return Ok(LinkOutcome::default()),
+    };
+    match resolved.state {
+        LinkState::Linked => {
+            info!(r#""{}" already linked"#, resolved.source.display());
+            return Ok(LinkOutcome::default());
+        }
+        LinkState::Conflict => {
+            error!("{} file already exists", resolved.destination.display());
+            return Ok(LinkOutcome::default());
+        }
+        LinkState::Dangling => {
+            error!(
+                "{} is a dangling symlink, fix it with `dots repair`",
+                resolved.destination.display()
+            );
+            return Ok(LinkOutcome::default());
+        }
+        LinkState::Unlinked => {}
+    }
+    let ResolvedLink {
+        source,
+        destination,
+        escalation,
+        ..
+    } = resolved;
+    let parent = destination
+        .parent()
+        .context("Could not get parent folder")?
+        .to_path_buf();
+    let created = missing_dirs(&parent);
+    let target = ctx.project.link_style(&link).target(&source, &destination);
+    // If sudo is required, defer the work to the privileged plan
+    if escalation.check(&destination)? {
+        let mut ops = vec![
+            PrivilegedOp::CreateDir(parent),
+            PrivilegedOp::Symlink {
+                source: target,
+                destination: destination.clone(),
+            },
+        ];
+        if let Some(perms) = link.perms {
+            if perms.user_owner.is_some() || perms.group_owner.is_some() {
+                let owner = format!(
+                    "{}:{}",
+                    perms.user_owner.unwrap_or_else(|| "".to_string()),
+                    perms.group_owner.unwrap_or_else(|| "".to_string())
+                );
+                ops.push(PrivilegedOp::Chown {
+                    owner,
+                    path: destination.clone(),
+                });
+            }
+            if let Some(user_code) = perms.user_code {
+                ops.push(PrivilegedOp::Chmod {
+                    mode: user_code,
+                    path: destination.clone(),
+                    recursive: true,
+                });
+            }
+            let source_parent = source
+                .parent()
+                .context("Could not get destination parent")?;
+            log::debug!("dest_parent: {}", source_parent.display());
+            ops.push(PrivilegedOp::Chmod {
+                mode: "o+rx".into(),
+                path: source_parent.to_path_buf(),
+                recursive: false,
+            });
+        }
+        return Ok(LinkOutcome {
+            ops: Some(ops),
+            created,
+        });
+    } else {
+        fs::create_dir_all(&parent).await.context(format!(
+            "Failed creating folder hierchy for {}",
+            &destination.display()
+        ))?;
+
+        fs::symlink(target, &destination).await?;
+        history::record(Operation::Create {
+            path: destination.clone(),
+        });
+        if let Some(perms) = link.perms {
+            let dest_str = destination
+                .to_str()
+                .context("Could not convert destination to string")?;
+            if let Some(user_code) = perms.user_code {
+                com_run("chmod", &["-R", &user_code, dest_str]).await?;
+            }
+            if perms.user_owner.is_some() || perms.group_owner.is_some() {
+                let owner_str = format!(
+                    "{}:{}",
+                    perms.user_owner.unwrap_or_default(),
+                    perms.group_owner.unwrap_or_default()
+                );
+                com_run("chown", &["-R", &owner_str, dest_str]).await?;
+            }
+        }
+    }
+    Ok(LinkOutcome { ops: None, created })
+}
+
+/// Links grouped into levels, where every link only depends on links in earlier levels
+#[derive(Debug)]
+pub struct LinkOrder {
+    pub levels: Vec<Vec<PendingLink>>,
+    pub dependencies: HashMap<String, Vec<String>>,
+}
+
+/// Order links so that links with a destination inside another link's destination, or with an
+/// explicit `after` dependency, are linked after them
+pub fn order_links(links: Vec<PendingLink>) -> Result<LinkOrder> {
+    let mut dependencies: HashMap<String, Vec<String>> = HashMap::new();
+    for PendingLink {
+        link, destination, ..
+    } in &links
+    {
+        let mut deps: Vec<String> = link
+            .after
+            .iter()
+            .flatten()
+            .filter(|x| links.iter().any(|y| &y.link.name == *x))
+            .cloned()
+            .collect();
+        for other in &links {
+            if other.link.name == link.name {
+                continue;
+            }
+            if &other.destination == destination {
+                bail!(
+                    "Links {} and {} have the same destination {}",
+                    link.name,
+                    other.link.name,
+                    destination.display()
+                );
+            }
+            if destination.starts_with(&other.destination) {
+                deps.push(other.link.name.clone());
+            }
+        }
+        dependencies.insert(link.name.clone(), deps);
+    }
+
+    let mut remaining = links;
+    let mut placed: HashSet<String> = HashSet::new();
+    let mut levels = Vec::new();
+    while !remaining.is_empty() {
+        let (level, rest): (Vec<PendingLink>, Vec<PendingLink>) =
+            remaining.into_iter().partition(|x| {
+                dependencies[&x.link.name]
+                    .iter()
+                    .all(|y| placed.contains(y))
+            });
+        if level.is_empty() {
+            bail!(
+                "Circular dependency between links {}",
+                rest.iter().map(|x| x.link.name.as_str()).join(", ")
+            );
+        }
+        placed.extend(level.iter().map(|x| x.link.name.clone()));
+        levels.push(level);
+        remaining = rest;
+    }
+    Ok(LinkOrder {
+        levels,
+        dependencies,
+    })
+}
+
 async fn com_run<I, S>(com: &str, args: I) -> Result<()>
 where
     I: IntoIterator<Item = S>,
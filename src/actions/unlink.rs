@@ -0,0 +1,85 @@
+use super::sync::{resolve_link, LinkState, ResolvedLink};
+use crate::{
+    backup,
+    link::Link,
+    privileged::{PrivilegedOp, PrivilegedPlan},
+    state::ProjectState,
+    ProjectContext,
+};
+use anyhow::Result;
+use log::*;
+use std::{fs::remove_file, path::PathBuf};
+
+/// Remove the symlinks dots made for `links`, leaving their sources in the project
+pub async fn unlink(ctx: &ProjectContext, links: &[Link]) -> Result<()> {
+    let mut state = ProjectState::load(&ctx.project.id)?;
+    let mut plan = PrivilegedPlan::new();
+    let mut emptied: Vec<Vec<PathBuf>> = Vec::new();
+    for link in links {
+        let ResolvedLink {
+            destination,
+            state: link_state,
+            escalation,
+            ..
+        } = match resolve_link(ctx, link) {
+            Ok(Some(x)) => x,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("Could not resolve {}: {}", link.name, e);
+                continue;
+            }
+        };
+        if link_state != LinkState::Linked {
+            debug!("{} is not linked", link.name);
+            continue;
+        }
+        let is_symlink = destination
+            .symlink_metadata()
+            .map(|x| x.file_type().is_symlink())
+            .unwrap_or(false);
+        if !is_symlink {
+            warn!(
+                "Not unlinking {}, {} is not a symlink",
+                link.name,
+                destination.display()
+            );
+            continue;
+        }
+        if let Err(e) = backup::snapshot(&destination, "unlink") {
+            error!("Not unlinking {}: {}", link.name, e);
+            continue;
+        }
+        match escalation.check(&destination) {
+            Ok(true) => {
+                let dirs = state.emptied_parents(&destination)?;
+                let mut ops = vec![PrivilegedOp::Remove(destination)];
+                ops.extend(dirs.iter().cloned().map(PrivilegedOp::RemoveDir));
+                plan.push(link.name.clone(), ops);
+                emptied.push(dirs);
+            }
+            Ok(false) => match remove_file(&destination) {
+                Ok(_) => {
+                    info!("Unlinked {}", link.name);
+                    for dir in state.remove_empty_parents(&destination)? {
+                        info!("Removed empty folder {}", dir.display());
+                    }
+                }
+                Err(e) => error!("Failed to unlink {}: {}", link.name, e),
+            },
+            Err(e) => error!("Failed to unlink {}: {}", link.name, e),
+        }
+    }
+    for ((name, res), dirs) in plan.run(ctx.sudo_program()).await?.into_iter().zip(emptied) {
+        match res {
+            Ok(_) => {
+                info!("Unlinked {}", name);
+                for dir in &dirs {
+                    info!("Removed empty folder {}", dir.display());
+                }
+                state.forget_dirs(&dirs);
+            }
+            Err(e) => error!("Failed to unlink {}: {}", name, e),
+        }
+    }
+    state.save(&ctx.project.id)
+}
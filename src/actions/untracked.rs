@@ -0,0 +1,117 @@
+use crate::{
+    backup,
+    config::ProjectConfig,
+    ignore::{IgnoreRules, IGNORE_FILE},
+    link::{Link, SourceFile, VariablePath},
+    util::{ask, confirm, expand_tilde},
+    ProjectContext,
+};
+use anyhow::{Context, Result};
+use colored::*;
+use log::*;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Files in the project root that belong to dots or git rather than any link
+pub const BUILT_IN: [&str; 3] = [".git", ".links.toml", IGNORE_FILE];
+
+fn normalise(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|x| !matches!(x, Component::CurDir))
+        .collect()
+}
+
+fn walk(
+    root: &Path,
+    relative: &Path,
+    ignore: &IgnoreRules,
+    sources: &HashSet<PathBuf>,
+    output: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        let is_dir = entry.file_type()?.is_dir();
+        if sources.contains(&path) || ignore.is_ignored(&path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            walk(root, &path, ignore, sources, output)?;
+        } else {
+            output.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Files in the project that no variant of any link uses, relative to the project
+pub fn find_untracked(ctx: &ProjectContext) -> Result<Vec<PathBuf>> {
+    let root = &ctx.project_config_path;
+    let ignore = ctx.ignore_rules()?;
+    let mut sources: HashSet<PathBuf> = ctx
+        .project
+        .links
+        .iter()
+        .flat_map(|link| link.src.clone().into_iter())
+        .map(|(_, _, src)| normalise(Path::new(&src)))
+        .collect();
+    sources.extend(BUILT_IN.iter().map(PathBuf::from));
+    let mut output = Vec::new();
+    walk(root, Path::new(""), &ignore, &sources, &mut output)?;
+    output.sort();
+    Ok(output)
+}
+
+/// List files nothing links to, optionally asking to add each one as a link or delete it
+pub async fn untracked(ctx: &ProjectContext, add: bool, delete: bool) -> Result<ProjectConfig> {
+    let mut project = ctx.project.clone();
+    let files = find_untracked(ctx)?;
+    if files.is_empty() {
+        info!("No untracked files in {}", ctx.project.name);
+        return Ok(project);
+    }
+    for file in files {
+        let name = file.to_string_lossy().to_string();
+        println!("{} {}", "untracked".yellow(), name);
+        let action = match (add, delete) {
+            (false, false) => continue,
+            (true, true) => ask("(a)dd, (d)elete or (s)kip?")?,
+            (true, false) => "a".into(),
+            (false, true) if confirm(&format!("Delete {}?", name))? => "d".into(),
+            (false, true) => continue,
+        };
+        match action.to_lowercase().as_str() {
+            "a" | "add" => {
+                let destination = ask(&format!(
+                    "Destination to link {} to (leave empty to skip):",
+                    name
+                ))?;
+                if destination.is_empty() {
+                    continue;
+                }
+                if project.links.iter().any(|x| x.name == name) {
+                    error!("A link named {} already exists", name);
+                    continue;
+                }
+                project.links.push(Link::new(
+                    name.clone(),
+                    VariablePath::from(expand_tilde(&destination)),
+                    SourceFile::Source {
+                        system: ctx.args.system.clone(),
+                        src: name.clone(),
+                    },
+                ));
+                info!("Added {}, sync to link it", name);
+            }
+            "d" | "delete" => {
+                backup::snapshot(&ctx.project_config_path.join(&file), "untracked")?;
+                fs::remove_file(ctx.project_config_path.join(&file))
+                    .context(format!("Could not delete {}", name))?;
+                info!("Deleted {}", name);
+            }
+            _ => {}
+        }
+    }
+    Ok(project)
+}
@@ -0,0 +1,255 @@
+use super::sync::{link_links, resolve_link, LinkState};
+use crate::{backup, history, privileged::Escalation, Args, ProjectContext};
+use anyhow::{Context, Result};
+use clap::ArgEnum;
+use futures::StreamExt;
+use inotify::{EventMask, EventStream, Inotify, WatchDescriptor, WatchMask, Watches};
+use log::*;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Time to wait for more events before acting on a change
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// What to do when a deployed link is replaced by a regular file
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    Report,
+    Relink,
+}
+
+fn watch_mask() -> WatchMask {
+    WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MODIFY
+        | WatchMask::CLOSE_WRITE
+        | WatchMask::MOVED_FROM
+        | WatchMask::MOVED_TO
+}
+
+/// The folders being watched, kept for the whole run and updated as the project and its links
+/// change, so no change is missed between syncs
+pub(crate) struct Watcher {
+    watches: Watches,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+}
+
+impl Watcher {
+    pub(crate) fn new(stream: &EventStream<[u8; 4096]>) -> Watcher {
+        Watcher {
+            watches: stream.watches(),
+            dirs: HashMap::new(),
+        }
+    }
+
+    /// Watch the folders `ctx` needs, adding new ones and removing those no longer needed
+    pub(crate) fn update(&mut self, ctx: &ProjectContext) -> Result<()> {
+        let wanted = watch_paths(ctx)?;
+        let unwanted: Vec<WatchDescriptor> = self
+            .dirs
+            .iter()
+            .filter(|(_, dir)| !wanted.contains(*dir))
+            .map(|(wd, _)| wd.clone())
+            .collect();
+        for wd in unwanted {
+            if let Some(dir) = self.dirs.remove(&wd) {
+                //Fails when the folder is gone, which already removed the watch
+                if let Err(e) = self.watches.remove(wd) {
+                    debug!("Could not stop watching {}: {}", dir.display(), e);
+                }
+            }
+        }
+        for dir in wanted {
+            if self.dirs.values().any(|x| x == &dir) {
+                continue;
+            }
+            match self.watches.add(&dir, watch_mask()) {
+                Ok(wd) => {
+                    self.dirs.insert(wd, dir);
+                }
+                Err(e) => warn!("Could not watch {}: {}", dir.display(), e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn project_dirs(dir: &Path, output: &mut HashSet<PathBuf>) -> Result<()> {
+    output.insert(dir.to_path_buf());
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && entry.file_name() != ".git" {
+            project_dirs(&entry.path(), output)?;
+        }
+    }
+    Ok(())
+}
+
+/// The project, the system config and the folders containing every deployed link
+fn watch_paths(ctx: &ProjectContext) -> Result<HashSet<PathBuf>> {
+    let mut dirs = HashSet::new();
+    project_dirs(&ctx.project_config_path, &mut dirs)?;
+    if let Some(parent) = ctx.system_config_path.parent() {
+        dirs.insert(parent.to_path_buf());
+    }
+    for link in &ctx.project.links {
+        if let Ok(Some(resolved)) = resolve_link(ctx, link) {
+            if let Some(parent) = resolved.destination.parent().filter(|x| x.is_dir()) {
+                dirs.insert(parent.to_path_buf());
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+/// Wait for a change, then collect changes until none come for `DEBOUNCE`. Returns the paths
+/// that changed, or `config` when too many changed to tell which
+pub(crate) async fn next_changes(
+    stream: &mut EventStream<[u8; 4096]>,
+    watcher: &mut Watcher,
+    config: &Path,
+) -> Result<HashSet<PathBuf>> {
+    let mut changed: HashSet<PathBuf> = HashSet::new();
+    let mut next = stream.next().await;
+    loop {
+        let event = next.context("inotify stopped producing events")??;
+        if event.mask.contains(EventMask::Q_OVERFLOW) {
+            warn!("Too many changes at once, syncing all links");
+            changed.insert(config.to_path_buf());
+        }
+        if event.mask.contains(EventMask::IGNORED) {
+            //The folder was removed, the next update watches it again if it comes back
+            watcher.dirs.remove(&event.wd);
+        } else if let Some(dir) = watcher.dirs.get(&event.wd) {
+            changed.insert(match event.name {
+                Some(name) => dir.join(name),
+                None => dir.clone(),
+            });
+        }
+        next = match tokio::time::timeout(DEBOUNCE, stream.next()).await {
+            Ok(x) => x,
+            Err(_) => return Ok(changed),
+        };
+    }
+}
+
+pub async fn watch(args: Args, policy: ConflictPolicy, jobs: Option<usize>) -> Result<()> {
+    let ctx = args.clone().try_to_context()?;
+    let config = ctx.project_config_path.join(".links.toml");
+    let links = ctx.project.links.clone();
+    link_links(ctx, links, jobs).await?;
+    history::commit()?;
+    let inotify = Inotify::init().context("Could not initialise inotify")?;
+    let mut stream = inotify.into_event_stream([0; 4096])?;
+    let mut watcher = Watcher::new(&stream);
+    info!("Watching {}", config.parent().unwrap_or(&config).display());
+    loop {
+        //A config saved half written is read again once it changes, keeping the old watches
+        match args.clone().try_to_context() {
+            Ok(ctx) => {
+                if let Err(e) = watcher.update(&ctx) {
+                    error!("Could not update watches: {}", e);
+                }
+            }
+            Err(e) => error!(
+                "Could not read the project, keeping the last watches: {}",
+                e
+            ),
+        }
+        let changed = next_changes(&mut stream, &mut watcher, &config).await?;
+        if let Err(e) = handle_changes(&args, &changed, policy, jobs).await {
+            error!("Error syncing changes: {}", e);
+        }
+        if let Err(e) = history::commit() {
+            error!("Could not write history: {:?}", e);
+        }
+    }
+}
+
+/// Sync the links whose sources changed, and relink or report destinations that were removed or
+/// replaced
+pub(crate) async fn handle_changes(
+    args: &Args,
+    changed: &HashSet<PathBuf>,
+    policy: ConflictPolicy,
+    jobs: Option<usize>,
+) -> Result<()> {
+    let ctx = args.clone().try_to_context()?;
+    let ignore = ctx.ignore_rules()?;
+    let changed: HashSet<&PathBuf> = changed
+        .iter()
+        .filter(|x| !ignore.is_ignored_within(x, x.is_dir()))
+        .collect();
+    if changed.contains(&ctx.project_config_path.join(".links.toml"))
+        || changed.contains(&ctx.system_config_path)
+    {
+        info!("Configuration changed, syncing all links");
+        let links = ctx.project.links.clone();
+        return link_links(ctx, links, jobs).await;
+    }
+
+    let mut affected = Vec::new();
+    for link in &ctx.project.links {
+        let source = match link.src.resolve(&ctx.system) {
+            Some(x) => ctx.project_config_path.join(x),
+            None => continue,
+        };
+        if changed
+            .iter()
+            .any(|x| x.starts_with(&source) || source.starts_with(x))
+        {
+            debug!("source of {} changed", link.name);
+            affected.push(link.clone());
+            continue;
+        }
+        let resolved = match resolve_link(&ctx, link) {
+            Ok(Some(x)) if changed.contains(&x.destination) => x,
+            _ => continue,
+        };
+        match resolved.state {
+            LinkState::Linked => continue,
+            LinkState::Unlinked => {
+                info!("{} was removed, relinking", resolved.destination.display());
+                affected.push(link.clone());
+                continue;
+            }
+            LinkState::Dangling => {
+                warn!(
+                    "{} is a dangling symlink, fix it with `dots repair`",
+                    resolved.destination.display()
+                );
+                continue;
+            }
+            LinkState::Conflict => {}
+        }
+        match policy {
+            ConflictPolicy::Relink if resolved.escalation == Escalation::NotNeeded => {
+                let destination = &resolved.destination;
+                backup::snapshot(destination, "watch")?;
+                if destination.is_dir() {
+                    fs::remove_dir_all(destination)?;
+                } else {
+                    fs::remove_file(destination)?;
+                }
+                warn!(
+                    "{} was replaced, backed it up and relinked",
+                    destination.display()
+                );
+                affected.push(link.clone());
+            }
+            _ => warn!(
+                "{} was replaced and is no longer linked to {}",
+                resolved.destination.display(),
+                link.name
+            ),
+        }
+    }
+    if affected.is_empty() {
+        return Ok(());
+    }
+    link_links(ctx, affected, jobs).await
+}
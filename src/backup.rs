@@ -0,0 +1,337 @@
+use crate::config::{get_data_loc, BackupRetention};
+use crate::file_actions::Attributes;
+use crate::history::{self, Operation};
+use crate::util::WritableConfig;
+use anyhow::{bail, Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// A single file, folder or symlink inside a backup
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct BackupEntry {
+    /// Location relative to the backed up path, empty for the path itself
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    pub mode: u32,
+    /// Hash of the contents of files
+    pub hash: Option<String>,
+    pub size: Option<u64>,
+    /// Target of symlinks
+    pub target: Option<PathBuf>,
+    /// Owner, times and extended attributes, missing from older backups
+    #[serde(default)]
+    pub attributes: Option<Attributes>,
+}
+
+/// Snapshot of a path taken before dots changed it
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Backup {
+    pub id: String,
+    /// Seconds since the unix epoch
+    pub time: u64,
+    pub path: PathBuf,
+    /// What was about to happen to the path
+    pub reason: String,
+    pub entries: Vec<BackupEntry>,
+}
+
+impl Backup {
+    pub fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.time)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct BackupIndex {
+    #[serde(default)]
+    backups: Vec<Backup>,
+}
+
+/// Content addressed store of backups, where file contents are kept once under their hash
+pub struct BackupStore {
+    root: PathBuf,
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 8192];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect())
+}
+
+/// Give a restored entry its permissions, and the owner, times and extended attributes it was
+/// backed up with
+fn restore_attributes(path: &Path, entry: &BackupEntry) -> Result<()> {
+    let mode = (entry.kind != EntryKind::Symlink).then_some(entry.mode);
+    match (&entry.attributes, mode) {
+        (Some(attributes), mode) => attributes.apply(path, mode),
+        (None, Some(mode)) => Ok(fs::set_permissions(path, fs::Permissions::from_mode(mode))?),
+        (None, None) => Ok(()),
+    }
+}
+
+/// Join an entry path onto `base`, without adding a trailing slash for the root entry
+pub fn entry_path(base: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(relative)
+    }
+}
+
+impl BackupStore {
+    pub fn open() -> Result<BackupStore> {
+        BackupStore::at(
+            get_data_loc()
+                .context("Failed to get data location")?
+                .join("backups"),
+        )
+    }
+
+    /// Store kept in the folder `root`
+    pub fn at(root: PathBuf) -> Result<BackupStore> {
+        fs::create_dir_all(root.join("objects"))?;
+        Ok(BackupStore { root })
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.root.join("index.toml")
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        self.root.join("objects").join(hash)
+    }
+
+    fn read_index(&self) -> Result<BackupIndex> {
+        if !self.index_path().exists() {
+            return Ok(BackupIndex::default());
+        }
+        BackupIndex::read_from_file(&self.index_path())
+    }
+
+    fn write_index(&self, index: &BackupIndex) -> Result<()> {
+        let temp = self.root.join("index.toml.tmp");
+        index.write_to_file(&temp)?;
+        fs::rename(&temp, self.index_path())?;
+        Ok(())
+    }
+
+    fn store_file(&self, path: &Path) -> Result<String> {
+        let hash = hash_file(path)?;
+        let object = self.object_path(&hash);
+        if !object.exists() {
+            let temp = self.root.join("objects").join(format!("{}.tmp", hash));
+            fs::copy(path, &temp)?;
+            fs::rename(&temp, &object)?;
+        }
+        Ok(hash)
+    }
+
+    fn collect(&self, root: &Path, relative: &Path, entries: &mut Vec<BackupEntry>) -> Result<()> {
+        let path = entry_path(root, relative);
+        let metadata = path.symlink_metadata()?;
+        let file_type = metadata.file_type();
+        let mode = metadata.permissions().mode();
+        let attributes = Some(Attributes::read(&path, &metadata)?);
+        if file_type.is_symlink() {
+            entries.push(BackupEntry {
+                path: relative.to_path_buf(),
+                kind: EntryKind::Symlink,
+                mode,
+                hash: None,
+                size: None,
+                target: Some(fs::read_link(&path)?),
+                attributes,
+            });
+        } else if file_type.is_dir() {
+            entries.push(BackupEntry {
+                path: relative.to_path_buf(),
+                kind: EntryKind::Dir,
+                mode,
+                hash: None,
+                size: None,
+                target: None,
+                attributes,
+            });
+            let mut children: Vec<_> = fs::read_dir(&path)?
+                .map(|x| Ok(x?.file_name()))
+                .collect::<Result<_>>()?;
+            children.sort();
+            for child in children {
+                self.collect(root, &relative.join(child), entries)?;
+            }
+        } else if file_type.is_file() {
+            entries.push(BackupEntry {
+                path: relative.to_path_buf(),
+                kind: EntryKind::File,
+                mode,
+                hash: Some(self.store_file(&path)?),
+                size: Some(metadata.len()),
+                target: None,
+                attributes,
+            });
+        } else {
+            warn!("Not backing up special file {}", path.display());
+        }
+        Ok(())
+    }
+
+    /// Back up `path` before it is changed, returning the id of the backup or `None` when there
+    /// is nothing at `path`
+    pub fn snapshot(&self, path: &Path, reason: &str) -> Result<Option<String>> {
+        if path.symlink_metadata().is_err() {
+            return Ok(None);
+        }
+        let mut entries = Vec::new();
+        self.collect(path, Path::new(""), &mut entries)
+            .context(format!("Could not back up {}", path.display()))?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let id = format!("{:x}", now.as_nanos());
+        let mut index = self.read_index()?;
+        index.backups.push(Backup {
+            id: id.clone(),
+            time: now.as_secs(),
+            path: path.to_path_buf(),
+            reason: reason.to_string(),
+            entries,
+        });
+        self.write_index(&index)?;
+        debug!("Backed up {} as {}", path.display(), id);
+        Ok(Some(id))
+    }
+
+    pub fn list(&self) -> Result<Vec<Backup>> {
+        Ok(self.read_index()?.backups)
+    }
+
+    /// Find a backup by its id, or a unique start of it
+    pub fn get(&self, id: &str) -> Result<Backup> {
+        let mut matching: Vec<Backup> = self
+            .list()?
+            .into_iter()
+            .filter(|x| x.id.starts_with(id))
+            .collect();
+        match matching.len() {
+            0 => bail!("Could not find backup {}", id),
+            1 => Ok(matching.remove(0)),
+            _ => bail!("{} matches more than one backup", id),
+        }
+    }
+
+    /// Contents of a backed up file
+    pub fn read(&self, entry: &BackupEntry) -> Result<Vec<u8>> {
+        let hash = entry.hash.as_ref().context("Entry is not a file")?;
+        fs::read(self.object_path(hash)).context(format!("Backup object {} is missing", hash))
+    }
+
+    /// Recreate a backup at `to`, which must not exist
+    pub fn restore(&self, backup: &Backup, to: &Path) -> Result<()> {
+        if to.symlink_metadata().is_ok() {
+            bail!("{} already exists", to.display());
+        }
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut dirs = Vec::new();
+        for entry in &backup.entries {
+            let path = entry_path(to, &entry.path);
+            match entry.kind {
+                EntryKind::Dir => {
+                    fs::create_dir(&path)?;
+                    dirs.push((path, entry));
+                    continue;
+                }
+                EntryKind::Symlink => symlink(
+                    entry.target.as_ref().context("Symlink has no target")?,
+                    &path,
+                )?,
+                EntryKind::File => fs::write(&path, self.read(entry)?)?,
+            }
+            restore_attributes(&path, entry)?;
+        }
+        //Folders last, once filling them can no longer change their times or need write access
+        for (dir, entry) in dirs.into_iter().rev() {
+            restore_attributes(&dir, entry)?;
+        }
+        Ok(())
+    }
+
+    /// Remove backups older than the retention period, except the newest ones for each path,
+    /// and then every object no backup uses. Returns the number of backups and objects removed.
+    pub fn gc(&self, retention: &BackupRetention) -> Result<(usize, usize)> {
+        let max_age = Duration::from_secs(retention.max_age_days() * 24 * 60 * 60);
+        let now = SystemTime::now();
+        let mut index = self.read_index()?;
+        let before = index.backups.len();
+        let mut kept: Vec<Backup> = Vec::new();
+        //Newest first, so the first backups seen for a path are the ones to keep
+        index.backups.sort_by(|a, b| b.id.cmp(&a.id));
+        for backup in index.backups {
+            let newer = kept.iter().filter(|x| x.path == backup.path).count();
+            let expired = now
+                .duration_since(backup.time())
+                .map(|x| x > max_age)
+                .unwrap_or(false);
+            if !expired || newer < retention.keep_last() {
+                kept.push(backup);
+            }
+        }
+        kept.reverse();
+        let removed_backups = before - kept.len();
+        index.backups = kept;
+        self.write_index(&index)?;
+
+        let used: HashSet<&str> = index
+            .backups
+            .iter()
+            .flat_map(|x| x.entries.iter().filter_map(|y| y.hash.as_deref()))
+            .collect();
+        let mut removed_objects = 0;
+        for object in fs::read_dir(self.root.join("objects"))? {
+            let object = object?;
+            if !used.contains(object.file_name().to_string_lossy().as_ref()) {
+                fs::remove_file(object.path())?;
+                removed_objects += 1;
+            }
+        }
+        Ok((removed_backups, removed_objects))
+    }
+}
+
+/// Back up `path` into the backup store before it is changed
+pub fn snapshot(path: &Path, reason: &str) -> Result<()> {
+    if let Some(id) = BackupStore::open()?.snapshot(path, reason)? {
+        info!("Backed up {} as {}", path.display(), id);
+        history::record(Operation::Backup {
+            path: path.to_path_buf(),
+            backup: id,
+        });
+    }
+    Ok(())
+}
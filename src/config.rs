@@ -1,5 +1,6 @@
 use crate::goals::Goal;
-use crate::link::{Link, System};
+use crate::history::{self, Operation};
+use crate::link::{Link, LinkStyle, System};
 use crate::packages::ProgramConfig;
 use crate::util::WritableConfig;
 use anyhow::{bail, Context, Result};
@@ -18,6 +19,8 @@ pub struct ProjectConfig {
     pub id: String,
     pub default: Option<System>,
     pub systems: Vec<System>,
+    /// How links are created, absolute when unset
+    pub link_style: Option<LinkStyle>,
     pub variables: Option<HashMap<String, String>>,
     pub goals: Option<HashMap<String, Goal>>,
     pub programs: Option<Vec<ProgramConfig>>,
@@ -43,17 +46,48 @@ impl ProjectConfig {
             variables: None,
             goals: None,
             programs: None,
+            link_style: None,
         }
     }
+
+    /// Style to create `link` with, from the link or otherwise the project
+    pub fn link_style(&self, link: &Link) -> LinkStyle {
+        link.link_style.or(self.link_style).unwrap_or_default()
+    }
+
     pub fn save(&self, ctx: &crate::ProjectContext) -> Result<()> {
-        self.write_to_file(&ctx.project_config_path.join(".links.toml"))
+        self.save_to(&ctx.project_config_path)
+    }
+
+    /// Write the config into the project folder `dir`
+    pub fn save_to(&self, dir: &Path) -> Result<()> {
+        write_recorded(self, &dir.join(".links.toml"))
+    }
+}
+
+/// Write `config` to `path`, recording the change in the history
+fn write_recorded(config: &impl WritableConfig, path: &Path) -> Result<()> {
+    let before = fs::read_to_string(path).ok();
+    config.write_to_file(path)?;
+    let after = fs::read_to_string(path)?;
+    if before.as_ref() != Some(&after) {
+        history::record(Operation::Config {
+            path: path.to_path_buf(),
+            before,
+            after,
+        });
     }
+    Ok(())
 }
 
 pub fn get_config_loc() -> Option<PathBuf> {
     ProjectDirs::from("com", "AusCyber", "dotfile-sync").map(|x| x.config_dir().to_path_buf())
 }
 
+pub fn get_data_loc() -> Option<PathBuf> {
+    ProjectDirs::from("com", "AusCyber", "dotfile-sync").map(|x| x.data_dir().to_path_buf())
+}
+
 pub fn get_sys_config(config_path: Option<impl AsRef<Path>>) -> Result<(PathBuf, SystemConfig)> {
     match config_path {
         Some(x) => Ok((
@@ -103,8 +137,11 @@ pub fn get_project_config(config_path: Option<&PathBuf>) -> Result<(PathBuf, Pro
     }
 }
 
+/// A project registered in the system config, keyed by its id
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ProjectOutput {
+    /// Name of the project, missing for projects registered by name before ids were used
+    pub name: Option<String>,
     pub system: Option<System>,
     pub path: PathBuf,
 }
@@ -112,8 +149,30 @@ pub struct ProjectOutput {
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct SystemConfig {
     pub default: Option<PathBuf>,
-    pub projects: HashMap<String, ProjectOutput>,
     pub sudo_program: Option<String>,
+    /// Ignore file applied to every project, defaults to `ignore` in the config folder
+    pub ignore_file: Option<PathBuf>,
+    pub projects: HashMap<String, ProjectOutput>,
+    pub backups: Option<BackupRetention>,
+}
+
+/// How long `dots backups gc` keeps backups for
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct BackupRetention {
+    /// Days to keep backups for, 30 by default
+    pub max_age_days: Option<u64>,
+    /// Backups to keep for each path however old they are, 1 by default
+    pub keep_last: Option<usize>,
+}
+
+impl BackupRetention {
+    pub fn max_age_days(&self) -> u64 {
+        self.max_age_days.unwrap_or(30)
+    }
+
+    pub fn keep_last(&self) -> usize {
+        self.keep_last.unwrap_or(1)
+    }
 }
 
 impl Default for SystemConfig {
@@ -127,15 +186,63 @@ impl SystemConfig {
             default: None,
             projects: HashMap::new(),
             sudo_program: None,
+            ignore_file: None,
+            backups: None,
         }
     }
 
-    pub fn get_project(&self, name: &str) -> Option<&ProjectOutput> {
-        self.projects.get(name)
+    /// Write the config to `path`, recording the change in the history
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_recorded(self, path)
+    }
+
+    /// Registration of a project, by its id or else the name it was registered under
+    pub fn get_project(&self, project: &ProjectConfig) -> Option<&ProjectOutput> {
+        self.projects.get(&project.id).or_else(|| {
+            self.projects
+                .get(&project.name)
+                .filter(|x| x.name.is_none())
+        })
     }
 
-    pub fn add_project(&mut self, name: String, path: PathBuf) {
-        self.projects
-            .insert(name, ProjectOutput { system: None, path });
+    /// Find a registered project by its id or name, returning its key
+    pub fn find_project(&self, query: &str) -> Result<(String, &ProjectOutput)> {
+        if let Some(project) = self.projects.get(query) {
+            return Ok((query.to_string(), project));
+        }
+        let mut matching: Vec<_> = self
+            .projects
+            .iter()
+            .filter(|(_, x)| x.name.as_deref() == Some(query))
+            .collect();
+        match matching.len() {
+            0 => bail!("No project named {} is managed", query),
+            1 => {
+                let (key, project) = matching.remove(0);
+                Ok((key.clone(), project))
+            }
+            _ => bail!("More than one project is named {}, use its id", query),
+        }
+    }
+
+    /// Register a project under its id, keeping the system set for it and replacing any
+    /// registration under its name
+    pub fn add_project(&mut self, project: &ProjectConfig, path: PathBuf) {
+        let system = self.get_project(project).and_then(|x| x.system.clone());
+        if self
+            .projects
+            .get(&project.name)
+            .map_or(false, |x| x.name.is_none())
+        {
+            self.projects.remove(&project.name);
+        }
+        self.projects.insert(
+            project.id.clone(),
+            ProjectOutput {
+                name: Some(project.name.clone()),
+                system,
+                path,
+            },
+        );
     }
 }
@@ -0,0 +1,217 @@
+use crate::file_actions::walk_files;
+use anyhow::Result;
+use colored::*;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// Lines of context around each change
+const CONTEXT: usize = 3;
+/// Largest diff table computed before giving up on a line by line diff
+const MAX_TABLE: usize = 16_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Same,
+    Removed,
+    Added,
+}
+
+fn diff_ops<'a>(old: &[&'a str], new: &[&'a str]) -> Option<Vec<(Op, &'a str)>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_mid, new_mid) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+    let (n, m) = (old_mid.len(), new_mid.len());
+    if (n + 1) * (m + 1) > MAX_TABLE {
+        return None;
+    }
+    //Longest common subsequence of the remaining lines
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut ops: Vec<_> = old[..prefix].iter().map(|x| (Op::Same, *x)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old_mid[i] == new_mid[j] {
+            ops.push((Op::Same, old_mid[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push((Op::Removed, old_mid[i]));
+            i += 1;
+        } else {
+            ops.push((Op::Added, new_mid[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old_mid[i..].iter().map(|x| (Op::Removed, *x)));
+    ops.extend(new_mid[j..].iter().map(|x| (Op::Added, *x)));
+    ops.extend(old[old.len() - suffix..].iter().map(|x| (Op::Same, *x)));
+    Some(ops)
+}
+
+/// Unified diff between two texts, `None` if they are the same
+pub fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> Option<String> {
+    if old == new {
+        return None;
+    }
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let mut output = format!("--- {}\n+++ {}\n", old_name, new_name)
+        .bold()
+        .to_string();
+    let ops = match diff_ops(&old_lines, &new_lines) {
+        Some(x) => x,
+        None => return Some(format!("Files {} and {} differ\n", old_name, new_name)),
+    };
+
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, (op, _))| *op != Op::Same)
+        .map(|(i, _)| i)
+        .collect();
+    //Group changes into hunks, merging ones with overlapping context
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for change in changes {
+        let start = change.saturating_sub(CONTEXT);
+        let end = (change + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    for (start, end) in hunks {
+        let count = |range: &[(Op, &str)], skip: Op| range.iter().filter(|x| x.0 != skip).count();
+        let old_start = count(&ops[..start], Op::Added);
+        let new_start = count(&ops[..start], Op::Removed);
+        let old_len = count(&ops[start..end], Op::Added);
+        let new_len = count(&ops[start..end], Op::Removed);
+        output.push_str(
+            &format!(
+                "@@ -{},{} +{},{} @@",
+                old_start + 1,
+                old_len,
+                new_start + 1,
+                new_len
+            )
+            .cyan()
+            .to_string(),
+        );
+        output.push('\n');
+        for (op, line) in &ops[start..end] {
+            let line = match op {
+                Op::Same => format!(" {}", line).normal(),
+                Op::Removed => format!("-{}", line).red(),
+                Op::Added => format!("+{}", line).green(),
+            };
+            output.push_str(&line.to_string());
+            output.push('\n');
+        }
+    }
+    Some(output)
+}
+
+/// Diff two files or folders, `None` if they are the same
+pub fn diff_paths(old: &Path, new: &Path) -> Result<Option<String>> {
+    let (old_meta, new_meta) = (old.symlink_metadata()?, new.symlink_metadata()?);
+    if old_meta.file_type().is_symlink() || new_meta.file_type().is_symlink() {
+        let target = |path: &Path, meta: &fs::Metadata| -> Result<String> {
+            Ok(if meta.file_type().is_symlink() {
+                format!("symlink to {}", fs::read_link(path)?.display())
+            } else {
+                "not a symlink".into()
+            })
+        };
+        let (old_target, new_target) = (target(old, &old_meta)?, target(new, &new_meta)?);
+        return Ok(if old_target == new_target {
+            None
+        } else {
+            Some(format!(
+                "{} is a {} while {} is a {}\n",
+                old.display(),
+                old_target,
+                new.display(),
+                new_target
+            ))
+        });
+    }
+    match (old_meta.is_dir(), new_meta.is_dir()) {
+        (false, false) => {
+            let (old_data, new_data) = (fs::read(old)?, fs::read(new)?);
+            if old_data == new_data {
+                return Ok(None);
+            }
+            Ok(
+                match (String::from_utf8(old_data), String::from_utf8(new_data)) {
+                    (Ok(old_text), Ok(new_text)) => unified_diff(
+                        &old_text,
+                        &new_text,
+                        &old.display().to_string(),
+                        &new.display().to_string(),
+                    ),
+                    _ => Some(format!(
+                        "Binary files {} and {} differ\n",
+                        old.display(),
+                        new.display()
+                    )),
+                },
+            )
+        }
+        (true, true) => {
+            let relative = |dir: &Path| -> Result<BTreeSet<_>> {
+                walk_files(dir)?
+                    .into_iter()
+                    .map(|x| Ok(x.strip_prefix(dir)?.to_path_buf()))
+                    .collect()
+            };
+            let (old_files, new_files) = (relative(old)?, relative(new)?);
+            let mut output = String::new();
+            for file in old_files.union(&new_files) {
+                match (old_files.contains(file), new_files.contains(file)) {
+                    (true, false) => {
+                        output.push_str(&format!("Only in {}: {}\n", old.display(), file.display()))
+                    }
+                    (false, true) => {
+                        output.push_str(&format!("Only in {}: {}\n", new.display(), file.display()))
+                    }
+                    _ => {
+                        if let Some(diff) = diff_paths(&old.join(file), &new.join(file))? {
+                            output.push_str(&diff);
+                        }
+                    }
+                }
+            }
+            Ok(if output.is_empty() {
+                None
+            } else {
+                Some(output)
+            })
+        }
+        (old_dir, _) => {
+            let kind = |dir| if dir { "directory" } else { "file" };
+            Ok(Some(format!(
+                "{} is a {} while {} is a {}\n",
+                old.display(),
+                kind(old_dir),
+                new.display(),
+                kind(!old_dir)
+            )))
+        }
+    }
+}
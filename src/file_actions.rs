@@ -1,8 +1,14 @@
-use anyhow::Result;
-use std::path::{Path, PathBuf};
-
-use futures::future::{BoxFuture, FutureExt};
-use tokio::fs;
+use crate::history::{self, Operation};
+use crate::ignore::IgnoreRules;
+use anyhow::{bail, Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fs::{Metadata, Permissions};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
 
 pub fn check_path(path: &Path) -> Result<PathBuf> {
     if !path.exists() {
@@ -18,19 +24,311 @@ pub fn check_path(path: &Path) -> Result<PathBuf> {
 
     Ok(path.to_path_buf())
 }
-pub fn recurse_copy<'a>(src: &'a Path, output_dest: &'a Path) -> BoxFuture<'a, Result<()>> {
-    async move {
-        fs::create_dir(&output_dest).await?;
-        let mut files = fs::read_dir(src).await?;
-        while let Some(entry) = files.next_entry().await? {
-            let path = entry.path();
-            if path.is_file() {
-                fs::copy(&path, output_dest.join(&path.file_name().unwrap())).await?;
-            } else {
-                recurse_copy(&path, &output_dest.join(&path.file_name().unwrap())).await?;
+/// List every file below `dir`, without following symlinks
+pub fn walk_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut output = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            output.extend(walk_files(&entry.path())?);
+        } else {
+            output.push(entry.path());
+        }
+    }
+    Ok(output)
+}
+
+/// Folders that creating `dir` would create, from the outermost to `dir` itself
+pub fn missing_dirs(dir: &Path) -> Vec<PathBuf> {
+    let mut missing: Vec<PathBuf> = dir
+        .ancestors()
+        .take_while(|x| !x.as_os_str().is_empty() && !x.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    missing.reverse();
+    missing
+}
+
+/// Canonicalize the part of `path` that exists, keeping the rest as it is
+pub fn canonicalize_existing(path: &Path) -> PathBuf {
+    for ancestor in path.ancestors() {
+        if let Ok(canonical) = ancestor.canonicalize() {
+            return match path.strip_prefix(ancestor) {
+                Ok(rest) if !rest.as_os_str().is_empty() => canonical.join(rest),
+                _ => canonical,
+            };
+        }
+    }
+    path.to_path_buf()
+}
+
+/// Path that leads from the folder `from` to `to`, where both are absolute
+pub fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<_> = from.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut output = PathBuf::new();
+    for _ in common..from.len() {
+        output.push(Component::ParentDir);
+    }
+    output.extend(&to[common..]);
+    if output.as_os_str().is_empty() {
+        output.push(".");
+    }
+    output
+}
+
+/// Path next to `path` that is used while replacing it
+pub fn temp_link(path: &Path) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".dots-tmp");
+    PathBuf::from(temp)
+}
+
+/// Swap whatever is at `destination` for a symlink to `target`, without it ever being missing
+pub fn replace_symlink(target: &Path, destination: &Path) -> Result<()> {
+    let previous = std::fs::read_link(destination).ok();
+    let temp = temp_link(destination);
+    symlink(target, &temp)?;
+    if let Err(e) = std::fs::rename(&temp, destination) {
+        std::fs::remove_file(&temp)?;
+        return Err(e.into());
+    }
+    history::record(match previous {
+        Some(from) => Operation::Retarget {
+            path: destination.to_path_buf(),
+            from,
+        },
+        None => Operation::Create {
+            path: destination.to_path_buf(),
+        },
+    });
+    Ok(())
+}
+
+/// Flush a file or every file in a folder to disk
+pub fn sync_tree(path: &Path) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            sync_tree(&entry?.path())?;
+        }
+    } else if !metadata.is_file() {
+        return Ok(());
+    }
+    std::fs::File::open(path)?.sync_all()?;
+    Ok(())
+}
+
+/// Copy a file, folder or symlink exactly, keeping its modes, ownership and timestamps
+pub async fn recurse_copy(src: &Path, output_dest: &Path) -> Result<()> {
+    copy_tree(src, output_dest, &IgnoreRules::default()).await
+}
+
+/// Copy a file, folder or symlink exactly, leaving out everything `ignore` matches at the
+/// destination
+pub async fn copy_tree(src: &Path, output_dest: &Path, ignore: &IgnoreRules) -> Result<()> {
+    let (src, output_dest, ignore) = (src.to_path_buf(), output_dest.to_path_buf(), ignore.clone());
+    tokio::task::spawn_blocking(move || {
+        Copier {
+            ignore: &ignore,
+            visited: HashSet::new(),
+        }
+        .copy(&src, &output_dest)
+    })
+    .await?
+}
+
+struct Copier<'a> {
+    ignore: &'a IgnoreRules,
+    /// Device and inode of every folder entered, to stop bind mount loops
+    visited: HashSet<(u64, u64)>,
+}
+
+impl Copier<'_> {
+    fn copy(&mut self, src: &Path, dest: &Path) -> Result<()> {
+        let metadata = std::fs::symlink_metadata(src)?;
+        let file_type = metadata.file_type();
+        if file_type.is_symlink() {
+            symlink(std::fs::read_link(src)?, dest)?;
+        } else if file_type.is_dir() {
+            if !self.visited.insert((metadata.dev(), metadata.ino())) {
+                warn!("Not copying {} again, it contains itself", src.display());
+                return Ok(());
+            }
+            if dest.starts_with(src) {
+                bail!("Cannot copy {} into itself", src.display());
+            }
+            std::fs::create_dir(dest)?;
+            for entry in std::fs::read_dir(src)? {
+                let entry = entry?;
+                let child = dest.join(entry.file_name());
+                if self.ignore.is_ignored(&child, entry.file_type()?.is_dir()) {
+                    info!("Leaving out ignored {}", entry.path().display());
+                    continue;
+                }
+                self.copy(&entry.path(), &child)?;
+            }
+        } else if file_type.is_file() {
+            std::fs::copy(src, dest)?;
+        } else {
+            warn!("Skipping special file {}", src.display());
+            return Ok(());
+        }
+        //Folders get their metadata after their contents, so read only ones can be filled
+        copy_metadata(src, dest, &metadata)
+            .context(format!("Could not copy attributes of {}", src.display()))
+    }
+}
+
+fn c_path(path: &Path) -> Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// Owner, times and extended attributes of a file, kept by copies and backups
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attributes {
+    pub uid: u32,
+    pub gid: u32,
+    pub atime: i64,
+    pub atime_nsec: i64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    #[serde(default)]
+    pub xattrs: Vec<Xattr>,
+}
+
+/// An extended attribute and its value
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Xattr {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+impl Attributes {
+    /// Attributes of `path`, where `metadata` was read without following symlinks
+    pub fn read(path: &Path, metadata: &Metadata) -> Result<Attributes> {
+        Ok(Attributes {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            atime: metadata.atime(),
+            atime_nsec: metadata.atime_nsec(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            xattrs: read_xattrs(path)?,
+        })
+    }
+
+    /// Give `path` these attributes, and the permissions in `mode` unless it is a symlink
+    pub fn apply(&self, path: &Path, mode: Option<u32>) -> Result<()> {
+        let path_c = c_path(path)?;
+        //Only root can usually give files away, so failing to is not an error
+        if unsafe { libc::lchown(path_c.as_ptr(), self.uid, self.gid) } != 0 {
+            debug!(
+                "Could not keep the owner of {}: {}",
+                path.display(),
+                std::io::Error::last_os_error()
+            );
+        }
+        for xattr in &self.xattrs {
+            let name = CString::new(xattr.name.as_bytes())?;
+            if unsafe {
+                libc::lsetxattr(
+                    path_c.as_ptr(),
+                    name.as_ptr(),
+                    xattr.value.as_ptr() as *const libc::c_void,
+                    xattr.value.len(),
+                    0,
+                )
+            } != 0
+            {
+                debug!(
+                    "Could not set attribute {} of {}",
+                    xattr.name,
+                    path.display()
+                );
             }
         }
+        if let Some(mode) = mode {
+            //After changing owner, which clears setuid bits
+            std::fs::set_permissions(path, Permissions::from_mode(mode & 0o7777))?;
+        }
+        let times = [
+            libc::timespec {
+                tv_sec: self.atime as libc::time_t,
+                tv_nsec: self.atime_nsec as _,
+            },
+            libc::timespec {
+                tv_sec: self.mtime as libc::time_t,
+                tv_nsec: self.mtime_nsec as _,
+            },
+        ];
+        if unsafe {
+            libc::utimensat(
+                libc::AT_FDCWD,
+                path_c.as_ptr(),
+                times.as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        } != 0
+        {
+            return Err(std::io::Error::last_os_error().into());
+        }
         Ok(())
     }
-    .boxed()
+}
+
+fn copy_metadata(src: &Path, dest: &Path, metadata: &Metadata) -> Result<()> {
+    let mode = (!metadata.file_type().is_symlink()).then(|| metadata.mode());
+    Attributes::read(src, metadata)?.apply(dest, mode)
+}
+
+/// Extended attributes of `path`, skipping any that can't be read
+fn read_xattrs(path: &Path) -> Result<Vec<Xattr>> {
+    let path_c = c_path(path)?;
+    let size = unsafe { libc::llistxattr(path_c.as_ptr(), std::ptr::null_mut(), 0) };
+    if size <= 0 {
+        return Ok(Vec::new());
+    }
+    let mut names = vec![0u8; size as usize];
+    let size = unsafe {
+        libc::llistxattr(
+            path_c.as_ptr(),
+            names.as_mut_ptr() as *mut libc::c_char,
+            names.len(),
+        )
+    };
+    if size < 0 {
+        return Ok(Vec::new());
+    }
+    let mut xattrs = Vec::new();
+    for name in names[..size as usize]
+        .split(|x| *x == 0)
+        .filter(|x| !x.is_empty())
+    {
+        let name = CString::new(name)?;
+        let len =
+            unsafe { libc::lgetxattr(path_c.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if len < 0 {
+            continue;
+        }
+        let mut value = vec![0u8; len as usize];
+        let len = unsafe {
+            libc::lgetxattr(
+                path_c.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+            )
+        };
+        if len < 0 {
+            continue;
+        }
+        value.truncate(len as usize);
+        match name.into_string() {
+            Ok(name) => xattrs.push(Xattr { name, value }),
+            Err(e) => debug!("Skipping attribute {:?} of {}", e, path.display()),
+        }
+    }
+    Ok(xattrs)
 }
@@ -0,0 +1,193 @@
+use crate::config::get_data_loc;
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A change dots made, with enough information to reverse it
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Operation {
+    /// A project or system config was written, `before` is `None` when it was created
+    Config {
+        path: PathBuf,
+        before: Option<String>,
+        after: String,
+    },
+    /// What was at `path` was saved in the backup store before it was changed
+    Backup { path: PathBuf, backup: String },
+    /// A file, folder or symlink was created where there was nothing
+    Create { path: PathBuf },
+    /// A folder was created to hold a link
+    CreateDir { path: PathBuf },
+    /// A file or folder was moved from `from` to `to`
+    Move { from: PathBuf, to: PathBuf },
+    /// A symlink that pointed at `from` was pointed somewhere else
+    Retarget { path: PathBuf, from: PathBuf },
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Config {
+                path, before: None, ..
+            } => write!(f, "created {}", path.display()),
+            Operation::Config { path, .. } => write!(f, "changed {}", path.display()),
+            Operation::Backup { path, backup } => {
+                write!(f, "backed up {} as {}", path.display(), backup)
+            }
+            Operation::Create { path } => write!(f, "created {}", path.display()),
+            Operation::CreateDir { path } => write!(f, "created folder {}", path.display()),
+            Operation::Move { from, to } => {
+                write!(f, "moved {} to {}", from.display(), to.display())
+            }
+            Operation::Retarget { path, from } => {
+                write!(f, "pointed {} away from {}", path.display(), from.display())
+            }
+        }
+    }
+}
+
+/// One run of a command that changed something
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub id: u64,
+    /// Seconds since the unix epoch
+    pub time: u64,
+    pub command: String,
+    /// Entries this entry reversed, for entries written by `dots undo`
+    pub undoes: Option<Vec<u64>>,
+    #[serde(default)]
+    pub operations: Vec<Operation>,
+}
+
+impl Entry {
+    pub fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.time)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct Log {
+    #[serde(default)]
+    entries: Vec<Entry>,
+}
+
+#[derive(Default)]
+struct Recorder {
+    command: String,
+    operations: Vec<Operation>,
+}
+
+lazy_static::lazy_static! {
+    static ref RECORDER: Mutex<Recorder> = Mutex::new(Recorder::default());
+}
+
+fn history_path() -> Result<PathBuf> {
+    Ok(get_data_loc()
+        .context("Failed to get data location")?
+        .join("history.toml"))
+}
+
+/// Every entry in the history, oldest first
+pub fn entries() -> Result<Vec<Entry>> {
+    let path = history_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    parse(&fs::read_to_string(&path)?).context(format!("Could not read history {}", path.display()))
+}
+
+/// Entries of a history file
+pub fn parse(text: &str) -> Result<Vec<Entry>> {
+    Ok(toml::from_str::<Log>(text)?.entries)
+}
+
+/// An entry as its own `[[entries]]` table, which can be appended to a history file
+pub fn format_entry(entry: Entry) -> Result<String> {
+    Ok(toml::to_string(&Log {
+        entries: vec![entry],
+    })?)
+}
+
+/// Add an entry to the end of the history. The file is only ever appended to
+pub fn append(command: String, undoes: Option<Vec<u64>>, operations: Vec<Operation>) -> Result<()> {
+    let id = entries()?.last().map(|x| x.id + 1).unwrap_or(1);
+    let entry = Entry {
+        id,
+        time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        command,
+        undoes,
+        operations,
+    };
+    let path = history_path()?;
+    fs::create_dir_all(path.parent().context("Could not get parent folder")?)?;
+    let text = format_entry(entry)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", text)?;
+    Ok(())
+}
+
+/// Remember an operation of the running command
+pub fn record(operation: Operation) {
+    debug!("history: {}", operation);
+    match RECORDER.lock() {
+        Ok(mut recorder) => recorder.operations.push(operation),
+        Err(_) => warn!("Could not record {} in the history", operation),
+    }
+}
+
+/// Write the operations recorded so far as an entry, if there are any
+pub fn commit() -> Result<()> {
+    let (command, operations) = match RECORDER.lock() {
+        Ok(mut recorder) => (
+            recorder.command.clone(),
+            std::mem::take(&mut recorder.operations),
+        ),
+        Err(_) => return Ok(()),
+    };
+    if operations.is_empty() {
+        return Ok(());
+    }
+    append(command, None, operations)
+}
+
+/// Write an entry marking `undoes` as reversed by the running command, with the operations
+/// recorded while reversing them. Entries written by an undo are never undone themselves
+pub fn commit_undo(undoes: Vec<u64>) -> Result<()> {
+    let (command, operations) = match RECORDER.lock() {
+        Ok(mut recorder) => (
+            recorder.command.clone(),
+            std::mem::take(&mut recorder.operations),
+        ),
+        Err(_) => (String::new(), Vec::new()),
+    };
+    append(command, Some(undoes), operations)
+}
+
+/// Records the operations of a command, writing them to the history when dropped
+pub struct Session;
+
+impl Session {
+    pub fn start(command: String) -> Session {
+        if let Ok(mut recorder) = RECORDER.lock() {
+            recorder.command = command;
+        }
+        Session
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Err(e) = commit() {
+            error!("Could not write history: {:?}", e);
+        }
+    }
+}
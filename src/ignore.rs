@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the ignore file at the project root
+pub const IGNORE_FILE: &str = ".dotsignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    //Patterns containing a slash only match from the base, others match any file name
+    anchored: bool,
+    //Folder the rule applies below, relative to the root
+    base: PathBuf,
+}
+
+/// Gitignore style patterns, where the last matching pattern decides if a path is ignored
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    //Absolute paths outside of the root are never ignored
+    root: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    pub fn parse(text: &str) -> Result<IgnoreRules> {
+        let mut rules = Vec::new();
+        for line in text.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(x) => (true, x),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(x) => (true, x),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let line = line.trim_start_matches('/');
+            rules.push(Rule {
+                pattern: Pattern::new(line).context(format!("Invalid ignore pattern {}", line))?,
+                negated,
+                dir_only,
+                anchored,
+                base: PathBuf::new(),
+            });
+        }
+        Ok(IgnoreRules {
+            root: PathBuf::new(),
+            rules,
+        })
+    }
+
+    /// Read patterns from a file, with no patterns if it does not exist
+    pub fn from_file(path: &Path) -> Result<IgnoreRules> {
+        if !path.exists() {
+            return Ok(IgnoreRules::default());
+        }
+        let text =
+            fs::read_to_string(path).context(format!("Could not read {}", path.display()))?;
+        IgnoreRules::parse(&text).context(format!("Could not parse {}", path.display()))
+    }
+
+    /// Set the folder absolute paths are matched relative to
+    pub fn with_root(mut self, root: &Path) -> IgnoreRules {
+        self.root = root.to_path_buf();
+        self
+    }
+
+    /// Only apply the patterns below `base`, relative to the root
+    pub fn scoped(mut self, base: &Path) -> IgnoreRules {
+        for rule in &mut self.rules {
+            rule.base = base.join(&rule.base);
+        }
+        self
+    }
+
+    pub fn extend(&mut self, other: IgnoreRules) {
+        self.rules.extend(other.rules);
+    }
+
+    fn relative<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        if path.is_absolute() {
+            path.strip_prefix(&self.root).ok()
+        } else {
+            Some(path)
+        }
+    }
+
+    /// Whether `path`, relative to the root or absolute, is ignored. Parent folders are not
+    /// checked, so walks should not descend into ignored folders.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let path = match self.relative(path) {
+            Some(x) => x,
+            None => return false,
+        };
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let relative = match path.strip_prefix(&rule.base) {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            let target = match relative.components().next_back() {
+                Some(Component::Normal(name)) if !rule.anchored => Path::new(name),
+                Some(Component::Normal(_)) => relative,
+                _ => continue,
+            };
+            if rule.pattern.matches_path_with(target, MATCH_OPTIONS) {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+
+    /// Whether `path` or any folder containing it is ignored
+    pub fn is_ignored_within(&self, path: &Path, is_dir: bool) -> bool {
+        let path = match self.relative(path) {
+            Some(x) => x,
+            None => return false,
+        };
+        path.ancestors()
+            .skip(1)
+            .filter(|x| !x.as_os_str().is_empty())
+            .any(|x| self.is_ignored(x, true))
+            || self.is_ignored(path, is_dir)
+    }
+}
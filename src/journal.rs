@@ -0,0 +1,144 @@
+use crate::config::get_data_loc;
+use crate::link::LinkStyle;
+use crate::util::WritableConfig;
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MoveStage {
+    /// The original is untouched, the destination may be partly written
+    Moving,
+    /// The destination is complete, the original may be partly removed
+    Moved,
+}
+
+/// A file being moved into the project and linked back
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PendingMove {
+    pub original: PathBuf,
+    pub destination: PathBuf,
+    /// Where the destination is copied to before being renamed into place
+    pub temp: PathBuf,
+    pub stage: MoveStage,
+    /// Style of the symlink left at the original location
+    #[serde(default)]
+    pub style: LinkStyle,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct Journal {
+    #[serde(default)]
+    moves: Vec<PendingMove>,
+}
+
+fn journal_path() -> Result<PathBuf> {
+    Ok(get_data_loc()
+        .context("Failed to get data location")?
+        .join("journal.toml"))
+}
+
+fn read_journal() -> Result<Journal> {
+    let path = journal_path()?;
+    if !path.exists() {
+        return Ok(Journal::default());
+    }
+    Journal::read_from_file(&path)
+}
+
+fn write_journal(journal: &Journal) -> Result<()> {
+    let path = journal_path()?;
+    fs::create_dir_all(path.parent().context("Could not get parent folder")?)?;
+    let temp = path.with_extension("toml.tmp");
+    journal.write_to_file(&temp)?;
+    fs::File::open(&temp)?.sync_all()?;
+    fs::rename(&temp, &path)?;
+    Ok(())
+}
+
+/// Record the current stage of a move, before acting on it
+pub fn record(pending: &PendingMove) -> Result<()> {
+    let mut journal = read_journal()?;
+    journal.moves.retain(|x| x.original != pending.original);
+    journal.moves.push(pending.clone());
+    write_journal(&journal)
+}
+
+/// Forget a move once it is complete
+pub fn finish(original: &Path) -> Result<()> {
+    let mut journal = read_journal()?;
+    journal.moves.retain(|x| x.original != original);
+    write_journal(&journal)
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    match path.symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+fn links_to(original: &Path, destination: &Path, style: LinkStyle) -> bool {
+    original
+        .symlink_metadata()
+        .map(|x| x.file_type().is_symlink())
+        .unwrap_or(false)
+        && fs::read_link(original)
+            .map(|x| x == style.target(destination, original))
+            .unwrap_or(false)
+}
+
+/// Roll an interrupted move back while the original is intact, otherwise finish it
+pub fn settle(pending: &PendingMove) -> Result<()> {
+    let PendingMove {
+        original,
+        destination,
+        temp,
+        stage,
+        style,
+    } = pending;
+    remove_path(temp)?;
+    if links_to(original, destination, *style) {
+        return finish(original);
+    }
+    let original_present = original.symlink_metadata().is_ok();
+    match stage {
+        MoveStage::Moving if original_present => {
+            remove_path(destination)?;
+            info!("Rolled back moving {}", original.display());
+        }
+        _ if destination.exists() => {
+            remove_path(original)?;
+            std::os::unix::fs::symlink(style.target(destination, original), original)?;
+            warn!(
+                "Finished moving {} to {}, check it is in the project with `dots untracked`",
+                original.display(),
+                destination.display()
+            );
+        }
+        _ => {
+            warn!(
+                "Could not recover moving {}, neither it nor {} exist",
+                original.display(),
+                destination.display()
+            );
+        }
+    }
+    finish(original)
+}
+
+/// Settle every move left unfinished by an earlier run
+pub fn recover() -> Result<()> {
+    for pending in read_journal()?.moves {
+        settle(&pending).context(format!(
+            "Could not recover moving {}",
+            pending.original.display()
+        ))?;
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use crate::file_actions::check_path;
+use crate::file_actions::{canonicalize_existing, check_path, relative_path};
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -54,6 +54,28 @@ impl VariablePath {
     }
 }
 
+/// Whether symlinks point at their sources by absolute path, or relative to their own folder
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStyle {
+    #[default]
+    Absolute,
+    Relative,
+}
+
+impl LinkStyle {
+    /// What a symlink at `link` should contain to point at `source`
+    pub fn target(self, source: &Path, link: &Path) -> PathBuf {
+        match self {
+            LinkStyle::Absolute => source.to_path_buf(),
+            LinkStyle::Relative => relative_path(
+                &canonicalize_existing(link.parent().unwrap_or_else(|| Path::new("/"))),
+                &canonicalize_existing(source),
+            ),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Perms {
     pub user_owner: Option<String>,
@@ -70,6 +92,12 @@ pub struct Link {
     pub sudo_required: Option<bool>,
     #[serde(flatten)]
     pub perms: Option<Perms>,
+    /// Names of links that have to be linked before this one
+    pub after: Option<Vec<String>>,
+    /// Gitignore style patterns for files inside the link's sources
+    pub ignore: Option<Vec<String>>,
+    /// Overrides the project's link style
+    pub link_style: Option<LinkStyle>,
 }
 
 impl Link {
@@ -80,6 +108,9 @@ impl Link {
             src: destination,
             sudo_required: None,
             perms: None,
+            after: None,
+            ignore: None,
+            link_style: None,
         }
     }
 }
@@ -14,18 +14,26 @@ use colored::*;
 use std::convert::TryInto;
 
 mod actions;
+mod backup;
 mod config;
+mod diff;
 mod file_actions;
 mod goals;
+mod history;
+mod ignore;
+mod journal;
 mod link;
 mod packages;
+mod privileged;
+mod state;
 #[cfg(test)]
 mod tests;
 mod util;
 
 use config::*;
+use ignore::{IgnoreRules, IGNORE_FILE};
 use link::{Link, System};
-use util::WritableConfig;
+use privileged::Escalation;
 
 #[derive(Parser, Clone)]
 #[clap(about = "Manage dotfiles")]
@@ -43,6 +51,12 @@ pub struct Args {
     project: Option<String>,
     #[clap(long, short, global = true)]
     system: Option<System>,
+    #[clap(
+        long,
+        global = true,
+        about = "Fail instead of escalating privileges through the sudo program"
+    )]
+    no_sudo: bool,
     #[clap(subcommand)]
     command: Command,
 }
@@ -65,6 +79,42 @@ impl ProjectContext {
             .find(|x| x.src.contains_path(stripped_path))
     }
 
+    pub fn sudo_program(&self) -> &str {
+        self.system_config.sudo_program.as_deref().unwrap_or("sudo")
+    }
+
+    pub fn escalation(&self, destination: &Path, sudo_required: Option<bool>) -> Escalation {
+        Escalation::decide(destination, sudo_required, self.args.no_sudo)
+    }
+
+    /// Ignore patterns from the project, the system config and every link, matched against
+    /// paths in the project
+    pub fn ignore_rules(&self) -> Result<IgnoreRules> {
+        let mut rules = IgnoreRules::from_file(&self.project_config_path.join(IGNORE_FILE))?;
+        let system_file = self
+            .system_config
+            .ignore_file
+            .clone()
+            .or_else(|| self.system_config_path.parent().map(|x| x.join("ignore")));
+        if let Some(system_file) = system_file {
+            rules.extend(IgnoreRules::from_file(&system_file)?);
+        }
+        for link in &self.project.links {
+            let patterns = match &link.ignore {
+                Some(x) => x.join("\n"),
+                None => continue,
+            };
+            for (_, _, src) in link.src.clone() {
+                rules.extend(
+                    IgnoreRules::parse(&patterns)
+                        .context(format!("Invalid ignore patterns for {}", link.name))?
+                        .scoped(Path::new(&src)),
+                );
+            }
+        }
+        Ok(rules.with_root(&self.project_config_path))
+    }
+
     pub fn in_project(&self, path: &str) -> Result<bool> {
         Ok(self
             .project_config_path
@@ -81,6 +131,10 @@ impl TryInto<ProjectContext> for Args {
     fn try_into(self) -> Result<ProjectContext> {
         let (system_config_file, system_config) = get_sys_config(self.config_file.as_ref())?;
         let current = std::env::current_dir()?;
+        let named = match &self.project {
+            Some(query) => Some(system_config.find_project(query)?.1.path.clone()),
+            None => None,
+        };
         let (path, proj_config) = get_project_config(
             self.project_path
                 .as_ref()
@@ -91,24 +145,14 @@ impl TryInto<ProjectContext> for Args {
                         None
                     }
                 })
-                .or_else(|| {
-                    self.project
-                        .clone()
-                        .and_then(|y| system_config.projects.get(&y))
-                        .map(|x| &x.path)
-                })
+                .or(named.as_ref())
                 .or(system_config.default.as_ref()),
         )?;
 
         let system = self
             .system
             .as_ref()
-            .or_else(|| {
-                system_config
-                    .get_project(&proj_config.name)?
-                    .system
-                    .as_ref()
-            })
+            .or_else(|| system_config.get_project(&proj_config)?.system.as_ref())
             .or(proj_config.default.as_ref())
             .cloned();
         Ok(ProjectContext {
@@ -137,6 +181,10 @@ enum Command {
         installed_programs: bool,
         #[clap(short = 'g')]
         goal: Option<String>,
+        #[clap(long, about = "Show what would be linked without changing anything")]
+        dry_run: bool,
+        #[clap(short, long, about = "Maximum number of links to sync at once")]
+        jobs: Option<usize>,
     },
     #[clap(about = "Move and link project")]
     Add {
@@ -145,41 +193,202 @@ enum Command {
         destination: Option<String>,
         #[clap(short, long)]
         name: Option<String>,
+        #[clap(short, long, about = "Add every file inside folders as its own link")]
+        recursive: bool,
+        #[clap(
+            short,
+            long,
+            multiple_occurrences = true,
+            about = "Glob of files to leave out"
+        )]
+        exclude: Vec<String>,
+    },
+    #[clap(about = "Move files already at link destinations into the project for this system")]
+    Adopt {
+        #[clap(short, long, about = "Adopt without asking")]
+        yes: bool,
+    },
+    #[clap(about = "Clone or copy a project, make it the default and link it")]
+    Bootstrap {
+        #[clap(about = "Git url or local path of the project")]
+        repo: String,
+        #[clap(
+            long,
+            about = "Where to put the project, a folder in home named after it by default"
+        )]
+        into: Option<PathBuf>,
+        #[clap(short, long, about = "Only link this goal")]
+        goal: Option<String>,
+        #[clap(short, long, about = "Link without asking")]
+        yes: bool,
     },
     #[clap(about = "Initalise project")]
-    Init { name: Option<String> },
-    #[clap(about = "Revert path")]
-    Revert { file: PathBuf },
+    Init {
+        name: Option<String>,
+        #[clap(long, about = "Propose links for the files already in the folder")]
+        scan: bool,
+        #[clap(
+            long,
+            arg_enum,
+            default_value = "home",
+            about = "How the files in the folder are laid out, used with --scan"
+        )]
+        layout: actions::Layout,
+    },
+    #[clap(about = "Revert path, link or goal")]
+    Revert {
+        file: Option<PathBuf>,
+        #[clap(short, long, about = "Revert the link of this name")]
+        link: Option<String>,
+        #[clap(short, long, about = "Revert every link in this goal")]
+        goal: Option<String>,
+    },
+    #[clap(about = "Remove the symlinks of links, leaving their files in the project")]
+    Unlink {
+        #[clap(about = "Names of the links to unlink, every link when empty")]
+        links: Vec<String>,
+        #[clap(short, long, about = "Unlink every link in this goal")]
+        goal: Option<String>,
+    },
+    #[clap(about = "Copy every link back into place as real files")]
+    Eject {
+        #[clap(long, about = "Delete the project config file after ejecting")]
+        remove_config: bool,
+    },
+    #[clap(about = "Create a project from a folder managed by another dotfile manager")]
+    Import {
+        #[clap(long, arg_enum)]
+        from: actions::ImportSource,
+        dir: PathBuf,
+    },
     #[clap(about = "Add project to system configuration")]
     Manage {
         #[clap(short, long)]
         default: bool,
     },
     #[clap(about = "Prune all removed files in the project")]
-    Prune,
+    Prune {
+        #[clap(long, about = "Show what would be removed without changing anything")]
+        dry_run: bool,
+        #[clap(short, long, about = "Prune without asking")]
+        yes: bool,
+    },
+    #[clap(
+        about = "Retarget dangling links, and links into managed projects that point elsewhere"
+    )]
+    Repair,
+    #[clap(about = "Move the project and point every deployed link at its new location")]
+    Relocate { new_path: PathBuf },
+    #[clap(
+        about = "List, inspect and restore backups of files dots changed",
+        subcommand
+    )]
+    Backups(actions::BackupsSubCommand),
+    #[clap(about = "Read and change project or system settings", subcommand)]
+    Config(actions::ConfigSubCommand),
+    #[clap(about = "List and configure managed projects", subcommand)]
+    Projects(actions::ProjectsSubCommand),
     #[clap(about = "Work with Goals", subcommand)]
     Goals(actions::goal::GoalSubCommand),
     Completion {
         #[clap(long, value_name = "SHELL", arg_enum)]
         shell: Shell,
     },
+    #[clap(
+        about = "List the links, goals, systems or projects the next word can be",
+        setting = clap::AppSettings::Hidden
+    )]
+    Complete {
+        #[clap(about = "The command line after dots, up to the word being completed")]
+        words: Vec<String>,
+    },
     #[clap(about = "List all links in the project")]
     List,
+    #[clap(about = "Browse links and goals, and sync, unlink or edit them interactively")]
+    Tui,
+    #[clap(about = "Show the state of every link in the project")]
+    Status,
+    #[clap(about = "Show how files at link destinations differ from the project")]
+    Diff {
+        link: Option<String>,
+        #[clap(
+            short,
+            long,
+            multiple_occurrences = true,
+            max_occurrences = 2,
+            about = "Compare the sources for two systems, or for the current system and this one"
+        )]
+        system: Vec<System>,
+    },
+    #[clap(about = "List files in the project that no link uses")]
+    Untracked {
+        #[clap(short, long, about = "Ask to add each file as a link")]
+        add: bool,
+        #[clap(short, long, about = "Ask to delete each file")]
+        delete: bool,
+    },
+    #[clap(about = "Show the changes dots made, newest first")]
+    History {
+        #[clap(short = 'n', long, about = "Only show this many entries")]
+        limit: Option<usize>,
+        #[clap(short, long, about = "Show how config files changed")]
+        verbose: bool,
+    },
+    #[clap(about = "Reverse the last changes dots made")]
+    Undo {
+        #[clap(default_value = "1", about = "Number of commands to undo")]
+        count: usize,
+        #[clap(short, long, about = "Undo without asking")]
+        yes: bool,
+    },
+    #[clap(about = "Keep links in sync while the project changes")]
+    Watch {
+        #[clap(
+            long,
+            arg_enum,
+            default_value = "report",
+            about = "What to do when a link is replaced by a regular file"
+        )]
+        on_conflict: actions::ConflictPolicy,
+        #[clap(short, long, about = "Maximum number of links to sync at once")]
+        jobs: Option<usize>,
+    },
 }
 
 #[tokio::main]
 pub async fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     let args = Args::parse();
+    //Completion runs on every TAB, so it must not finish interrupted moves or write history
+    if let Command::Complete { words } = &args.command {
+        actions::complete(words);
+        return Ok(());
+    }
+    if let Err(e) = journal::recover() {
+        error!("{:?}", e);
+    }
+    let _history = history::Session::start(env::args().skip(1).collect::<Vec<_>>().join(" "));
     match args.command.clone() {
         Command::Completion { shell } => {
-            generate(shell, &mut Args::into_app(), "dots", &mut std::io::stdout())
+            generate(shell, &mut Args::into_app(), "dots", &mut std::io::stdout());
+            print!("{}", actions::completion_script(shell));
         }
+        Command::Complete { .. } => {}
         Command::Sync {
             goal,
             installed_programs,
+            dry_run,
+            jobs,
+        } => {
+            actions::sync(args.try_into()?, goal, installed_programs, dry_run, jobs).await?;
+        }
+        Command::Bootstrap {
+            repo,
+            into,
+            goal,
+            yes,
         } => {
-            actions::sync(args.try_into()?, goal, installed_programs).await?;
+            actions::bootstrap(args, repo, into, goal, yes).await?;
         }
         Command::Manage { default } => {
             let ctx = args.try_to_context()?;
@@ -187,23 +396,30 @@ pub async fn main() -> Result<()> {
                 "Failure managing {}",
                 ctx.project_config_path.display()
             ))?;
-            config.write_to_file(&ctx.system_config_path)?;
+            config.save(&ctx.system_config_path)?;
             info!("Managed {}", ctx.project.name);
         }
         Command::Add {
             src,
             destination,
             name,
+            recursive,
+            exclude,
         } => {
             let ctx = args.try_to_context()?;
-            let config = actions::add(&ctx, src, destination, name)
+            let config = actions::add(&ctx, src, destination, name, recursive, exclude)
                 .await
                 .context("Failure adding link")?;
             config.save(&ctx)?;
         }
-        Command::Init { name } => {
+        Command::Adopt { yes } => {
+            let ctx = args.try_to_context()?;
+            let config = actions::adopt(&ctx, yes).await?;
+            config.save(&ctx)?;
+        }
+        Command::Init { name, scan, layout } => {
             let dir = env::current_dir()?;
-            let project = ProjectConfig::new(
+            let mut project = ProjectConfig::new(
                 name.unwrap_or(
                     dir.file_name()
                         .and_then(|x| x.to_str())
@@ -212,7 +428,31 @@ pub async fn main() -> Result<()> {
                 ),
                 &dir,
             );
-            project.write_to_file(&dir.join(".links.toml"))?;
+            if scan {
+                match actions::scan(&dir, layout)? {
+                    Some(links) => project.links = links,
+                    None => {
+                        info!("Nothing written");
+                        return Ok(());
+                    }
+                }
+            }
+            project.save_to(&dir)?;
+        }
+        Command::Import { from, dir } => {
+            let dir = dir.canonicalize()?;
+            let config_path = dir.join(".links.toml");
+            if config_path.exists() {
+                anyhow::bail!("{} already exists", config_path.display());
+            }
+            let home = PathBuf::from(env::var("HOME")?);
+            let project = actions::import(&dir, &home, from)?;
+            project.save_to(&dir)?;
+            info!(
+                "Imported {} links into {}",
+                project.links.len(),
+                config_path.display()
+            );
         }
         Command::List => {
             let ctx = args.try_to_context()?;
@@ -221,14 +461,109 @@ pub async fn main() -> Result<()> {
                 print!("{}", link);
             }
         }
-        Command::Revert { file } => {
+        Command::Watch { on_conflict, jobs } => {
+            actions::watch(args, on_conflict, jobs).await?;
+        }
+        Command::History { limit, verbose } => actions::history(limit, verbose)?,
+        Command::Undo { count, yes } => {
+            let (_, system_config) = get_sys_config(args.config_file.as_ref())?;
+            actions::undo(&system_config, args.no_sudo, count, yes).await?;
+        }
+        Command::Diff { link, system } => {
+            // The diff's own --system shadows the global one, which clap fills with its first value
+            let ctx = Args {
+                system: None,
+                ..args
+            }
+            .try_to_context()?;
+            actions::diff(&ctx, link, &system)?;
+        }
+        Command::Untracked { add, delete } => {
             let ctx = args.try_to_context()?;
-            let config = actions::revert(&ctx, &file).await?;
+            actions::untracked(&ctx, add, delete).await?.save(&ctx)?;
+        }
+        Command::Tui => {
+            actions::interactive(args).await?;
+        }
+        Command::Status => {
+            let ctx = args.try_to_context()?;
+            actions::status(&ctx)?;
+        }
+        Command::Revert { file, link, goal } => {
+            let target = match (file, link, goal) {
+                (Some(file), None, None) => actions::RevertTarget::Path(file),
+                (None, Some(link), None) => actions::RevertTarget::Link(link),
+                (None, None, Some(goal)) => actions::RevertTarget::Goal(goal),
+                _ => anyhow::bail!("Specify exactly one of a file, --link or --goal"),
+            };
+            let ctx = args.try_to_context()?;
+            let config = actions::revert(&ctx, target).await?;
             config.save(&ctx)?;
         }
-        Command::Prune => {
+        Command::Unlink { links, goal } => {
+            let ctx = args.try_to_context()?;
+            let selected = match goal {
+                Some(goal) => ctx
+                    .project
+                    .goals
+                    .as_ref()
+                    .and_then(|x| x.get(&goal))
+                    .context("Could not find goal")?
+                    .get_links(&ctx)?,
+                None if links.is_empty() => ctx.project.links.clone(),
+                None => links
+                    .iter()
+                    .map(|name| {
+                        ctx.project
+                            .links
+                            .iter()
+                            .find(|x| &x.name == name)
+                            .cloned()
+                            .context(format!("Could not find link {}", name))
+                    })
+                    .collect::<Result<_>>()?,
+            };
+            actions::unlink(&ctx, &selected).await?;
+        }
+        Command::Repair => {
+            let ctx = args.try_to_context()?;
+            actions::repair(&ctx).await?;
+        }
+        Command::Relocate { new_path } => {
+            let ctx = args.try_to_context()?;
+            let config = actions::relocate(&ctx, &new_path).await?;
+            config.save(&ctx.system_config_path)?;
+        }
+        Command::Config(command) => {
+            if command.global() {
+                let (path, system_config) = get_sys_config(args.config_file.as_ref())?;
+                if let Some(config) = actions::system_settings(&system_config, command)? {
+                    config.save(&path)?;
+                }
+            } else {
+                let ctx = args.try_to_context()?;
+                if let Some(config) = actions::project_settings(&ctx.project, command)? {
+                    config.save(&ctx)?;
+                }
+            }
+        }
+        Command::Projects(command) => {
+            let (path, system_config) = get_sys_config(args.config_file.as_ref())?;
+            if let Some(config) = actions::projects(&system_config, command)? {
+                config.save(&path)?;
+            }
+        }
+        Command::Backups(command) => {
+            let (_, system_config) = get_sys_config(args.config_file.as_ref())?;
+            actions::backups(&system_config, command)?;
+        }
+        Command::Eject { remove_config } => {
+            let ctx = args.try_to_context()?;
+            actions::eject(&ctx, remove_config).await?;
+        }
+        Command::Prune { dry_run, yes } => {
             let ctx = args.try_to_context()?;
-            actions::prune(&ctx)?.save(&ctx)?;
+            actions::prune(&ctx, dry_run, yes).await?.save(&ctx)?;
         }
         Command::Goals(command) => {
             let ctx = args.try_to_context()?;
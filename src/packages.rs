@@ -10,7 +10,7 @@ enum GoalType {
     InlineGoal(Goal),
     GoalName { goal: String },
     LinkName { link_name: String },
-    Link(Link),
+    Link(Box<Link>),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -43,7 +43,7 @@ impl ProgramConfig {
                 .cloned()
                 .into_iter()
                 .collect()),
-            GoalType::Link(link) => Ok(vec![link.clone()]),
+            GoalType::Link(link) => Ok(vec![link.as_ref().clone()]),
         }
     }
     pub async fn package_installed(&self) -> Result<bool> {
@@ -0,0 +1,308 @@
+use crate::file_actions::missing_dirs;
+use crate::history::{self, Operation};
+use anyhow::{bail, Context, Result};
+use log::*;
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+/// Whether a destination has to be modified through the sudo program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escalation {
+    NotNeeded,
+    Required,
+    Refused,
+}
+
+impl Escalation {
+    pub fn decide(destination: &Path, sudo_required: Option<bool>, no_sudo: bool) -> Escalation {
+        if !sudo_required.unwrap_or(false) && is_writable(destination) {
+            Escalation::NotNeeded
+        } else if no_sudo {
+            Escalation::Refused
+        } else {
+            Escalation::Required
+        }
+    }
+
+    /// Whether the sudo program should be used, failing when escalation was refused
+    pub fn check(self, destination: &Path) -> Result<bool> {
+        match self {
+            Escalation::NotNeeded => Ok(false),
+            Escalation::Required => Ok(true),
+            Escalation::Refused => bail!(
+                "{} requires privilege escalation, but --no-sudo is set",
+                destination.display()
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Escalation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Escalation::NotNeeded => write!(f, "no sudo"),
+            Escalation::Required => write!(f, "sudo"),
+            Escalation::Refused => write!(f, "needs sudo, refused by --no-sudo"),
+        }
+    }
+}
+
+/// Check if the current user can create or remove `path`, by checking the nearest existing
+/// ancestor of its parent folder
+pub fn is_writable(path: &Path) -> bool {
+    let ancestor = match path
+        .parent()
+        .and_then(|x| x.ancestors().find(|x| x.exists()))
+    {
+        Some(x) => x,
+        None => return false,
+    };
+    match CString::new(ancestor.as_os_str().as_bytes()) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 },
+        Err(_) => false,
+    }
+}
+
+/// Filesystem operation that has to be run through the sudo program
+#[derive(Debug, Clone)]
+pub enum PrivilegedOp {
+    CreateDir(PathBuf),
+    Symlink {
+        source: PathBuf,
+        destination: PathBuf,
+    },
+    Chown {
+        owner: String,
+        path: PathBuf,
+    },
+    Chmod {
+        mode: String,
+        path: PathBuf,
+        recursive: bool,
+    },
+    Remove(PathBuf),
+    /// Remove a folder and everything in it
+    RemoveAll(PathBuf),
+    /// Remove an empty folder
+    RemoveDir(PathBuf),
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    /// Rename over `to`, replacing it rather than moving into it when it is a folder
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+}
+
+fn path_str(path: &Path) -> Result<String> {
+    path.to_str()
+        .map(String::from)
+        .context(format!("Could not convert {} to string", path.display()))
+}
+
+impl PrivilegedOp {
+    fn to_args(&self) -> Result<Vec<String>> {
+        use PrivilegedOp::*;
+        Ok(match self {
+            CreateDir(path) => vec!["mkdir".into(), "-p".into(), path_str(path)?],
+            Symlink {
+                source,
+                destination,
+            } => vec![
+                "ln".into(),
+                "-s".into(),
+                path_str(source)?,
+                path_str(destination)?,
+            ],
+            Chown { owner, path } => vec![
+                "chown".into(),
+                "-h".into(),
+                "-R".into(),
+                owner.clone(),
+                path_str(path)?,
+            ],
+            Chmod {
+                mode,
+                path,
+                recursive,
+            } => {
+                let mut args = vec!["chmod".to_string()];
+                if *recursive {
+                    args.push("-R".into());
+                }
+                args.push(mode.clone());
+                args.push(path_str(path)?);
+                args
+            }
+            Remove(path) => vec!["rm".into(), "-f".into(), path_str(path)?],
+            RemoveAll(path) => vec!["rm".into(), "-rf".into(), path_str(path)?],
+            RemoveDir(path) => vec!["rmdir".into(), path_str(path)?],
+            Copy { from, to } => vec![
+                "cp".into(),
+                "-R".into(),
+                "-p".into(),
+                path_str(from)?,
+                path_str(to)?,
+            ],
+            //rename(2) through perl, as mv can't be told not to move into folders portably
+            Rename { from, to } => vec![
+                "perl".into(),
+                "-e".into(),
+                r#"rename $ARGV[0], $ARGV[1] or die "$!\n""#.into(),
+                path_str(from)?,
+                path_str(to)?,
+            ],
+        })
+    }
+}
+
+/// How the operations of an entry are recorded in the history, read before they run. Renames
+/// only ever put a temporary symlink in place, so the symlink is recorded where it ends up.
+/// Folders are recorded when they don't exist yet, unless an earlier entry in `dirs` creates
+/// them. Removals are backed up before they run, and permissions are not undone
+fn history(ops: &[PrivilegedOp], dirs: &mut HashSet<PathBuf>) -> Vec<Operation> {
+    use PrivilegedOp::*;
+    let renamed: Vec<&PathBuf> = ops
+        .iter()
+        .filter_map(|x| match x {
+            Rename { from, .. } => Some(from),
+            _ => None,
+        })
+        .collect();
+    ops.iter()
+        .flat_map(|op| match op {
+            CreateDir(path) => missing_dirs(path)
+                .into_iter()
+                .filter(|x| dirs.insert(x.clone()))
+                .map(|path| Operation::CreateDir { path })
+                .collect(),
+            Symlink { destination, .. } if !renamed.contains(&destination) => {
+                vec![Operation::Create {
+                    path: destination.clone(),
+                }]
+            }
+            Copy { to, .. } => vec![Operation::Create { path: to.clone() }],
+            Rename { to, .. } => vec![match std::fs::read_link(to) {
+                Ok(from) => Operation::Retarget {
+                    path: to.clone(),
+                    from,
+                },
+                Err(_) => Operation::Create { path: to.clone() },
+            }],
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r#"'\''"#))
+}
+
+/// All privileged work for a single run, executed through one invocation of the sudo program
+#[derive(Debug, Default)]
+pub struct PrivilegedPlan {
+    entries: Vec<(String, Vec<PrivilegedOp>)>,
+}
+
+impl PrivilegedPlan {
+    pub fn new() -> PrivilegedPlan {
+        PrivilegedPlan::default()
+    }
+
+    pub fn push(&mut self, link_name: String, ops: Vec<PrivilegedOp>) {
+        self.entries.push((link_name, ops));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Generate a shell script running the commands of each entry joined by `&&`, reporting the
+    /// outcome of each entry on stdout. `set -e` can't be used, as shells ignore it in a group
+    /// that is followed by `&&`
+    pub fn script(&self) -> Result<String> {
+        let mut script = String::new();
+        for (index, (_, ops)) in self.entries.iter().enumerate() {
+            let mut commands = Vec::new();
+            for op in ops {
+                commands.push(
+                    op.to_args()?
+                        .iter()
+                        .map(|x| shell_quote(x))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                );
+            }
+            if commands.is_empty() {
+                commands.push("true".into());
+            }
+            script.push_str(&format!(
+                "{{ {}; }} && echo 'dots-ok {1}' || echo 'dots-fail {1}'\n",
+                commands.join(" && "),
+                index
+            ));
+        }
+        Ok(script)
+    }
+
+    /// Run the plan, returning the result for each link in the order they were pushed
+    pub async fn run(self, sudo_program: &str) -> Result<Vec<(String, Result<()>)>> {
+        if self.is_empty() {
+            return Ok(Vec::new());
+        }
+        let script = self.script()?;
+        let mut dirs = HashSet::new();
+        let recorded: Vec<Vec<Operation>> = self
+            .entries
+            .iter()
+            .map(|x| history(&x.1, &mut dirs))
+            .collect();
+        debug!("privileged script:\n{}", script);
+        let output = crate::util::run_command(sudo_program, ["sh", "-c", &script])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .context(format!("Could not run {}", sudo_program))?
+            .wait_with_output()
+            .await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut results: Vec<Option<bool>> = vec![None; self.entries.len()];
+        for line in stdout.lines() {
+            let (success, index) = match line.split_once(' ') {
+                Some(("dots-ok", index)) => (true, index),
+                Some(("dots-fail", index)) => (false, index),
+                _ => continue,
+            };
+            if let Some(result) = index.parse::<usize>().ok().and_then(|x| results.get_mut(x)) {
+                *result = Some(success);
+            }
+        }
+        if results.iter().all(Option::is_none) && !output.status.success() {
+            bail!("{} exited with {}", sudo_program, output.status);
+        }
+        Ok(self
+            .entries
+            .into_iter()
+            .zip(results)
+            .zip(recorded)
+            .map(|(((name, _), result), recorded)| {
+                let result = match result {
+                    Some(true) => {
+                        recorded.into_iter().for_each(history::record);
+                        Ok(())
+                    }
+                    Some(false) => Err(anyhow::anyhow!(
+                        "privileged command failed, see output above"
+                    )),
+                    None => Err(anyhow::anyhow!("privileged commands did not run")),
+                };
+                (name, result)
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,89 @@
+use crate::config::get_data_loc;
+use crate::util::WritableConfig;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What dots has changed on this machine for a project, kept outside of the project itself
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ProjectState {
+    /// Folders created while linking, which can be removed again once empty
+    #[serde(default)]
+    pub created_dirs: Vec<PathBuf>,
+}
+
+fn state_path(project_id: &str) -> Result<PathBuf> {
+    Ok(get_data_loc()
+        .context("Failed to get data location")?
+        .join("state")
+        .join(format!("{}.toml", project_id)))
+}
+
+impl ProjectState {
+    pub fn load(project_id: &str) -> Result<ProjectState> {
+        let path = state_path(project_id)?;
+        if !path.exists() {
+            return Ok(ProjectState::default());
+        }
+        ProjectState::read_from_file(&path)
+    }
+
+    pub fn save(&self, project_id: &str) -> Result<()> {
+        let path = state_path(project_id)?;
+        fs::create_dir_all(path.parent().context("Could not get parent folder")?)?;
+        self.write_to_file(&path)
+    }
+
+    pub fn record_dirs(&mut self, dirs: impl IntoIterator<Item = PathBuf>) {
+        for dir in dirs {
+            if !self.created_dirs.contains(&dir) {
+                self.created_dirs.push(dir);
+            }
+        }
+    }
+
+    /// The folders above `path` that dots created and that only hold `path`, innermost first,
+    /// to remove with escalated privileges after `path`
+    pub fn emptied_parents(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let mut emptied = Vec::new();
+        let mut child = path;
+        for dir in path.ancestors().skip(1) {
+            if !self.created_dirs.iter().any(|x| x == dir) || !dir.exists() {
+                break;
+            }
+            for entry in fs::read_dir(dir)? {
+                if entry?.path() != child {
+                    return Ok(emptied);
+                }
+            }
+            emptied.push(dir.to_path_buf());
+            child = dir;
+        }
+        Ok(emptied)
+    }
+
+    /// Forget folders that were removed
+    pub fn forget_dirs(&mut self, dirs: &[PathBuf]) {
+        self.created_dirs.retain(|x| !dirs.contains(x));
+    }
+
+    /// Remove the folders above `path` that dots created and that are now empty
+    pub fn remove_empty_parents(&mut self, path: &Path) -> Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+        for dir in path.ancestors().skip(1) {
+            if !self.created_dirs.iter().any(|x| x == dir) {
+                break;
+            }
+            if dir.exists() {
+                if fs::read_dir(dir)?.next().is_some() {
+                    break;
+                }
+                fs::remove_dir(dir)?;
+                removed.push(dir.to_path_buf());
+            }
+            self.created_dirs.retain(|x| x != dir);
+        }
+        Ok(removed)
+    }
+}
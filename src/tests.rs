@@ -1,3 +1,50 @@
+use crate::{
+    config::{ProjectConfig, SystemConfig},
+    Args, ProjectContext,
+};
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use tokio::sync::{Mutex, MutexGuard};
+
+static DATA_HOME: Once = Once::new();
+
+lazy_static::lazy_static! {
+    static ref DATA_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Held by tests that change the data folder, so they don't race on its files
+async fn lock_data() -> MutexGuard<'static, ()> {
+    DATA_LOCK.lock().await
+}
+
+/// An empty folder for a test, removing what an earlier run left. Backups, history and state are
+/// kept in a test data folder rather than the user's
+fn temp_dir(name: &str) -> PathBuf {
+    DATA_HOME.call_once(|| {
+        let home = std::env::temp_dir().join("dots-test-home");
+        let _ = std::fs::remove_dir_all(&home);
+        std::env::set_var("XDG_DATA_HOME", home.join("data"));
+        std::env::set_var("XDG_CONFIG_HOME", home.join("config"));
+    });
+    let dir = std::env::temp_dir().join(format!("dots-test-{}", name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.canonicalize().unwrap()
+}
+
+/// Context for `project` in the folder `dir`, with an empty system config
+fn context(dir: &Path, project: ProjectConfig) -> ProjectContext {
+    ProjectContext {
+        args: Args::try_parse_from(["dots", "list"]).unwrap(),
+        project,
+        project_config_path: dir.to_path_buf(),
+        system_config: SystemConfig::new(),
+        system_config_path: dir.join("config.toml"),
+        system: None,
+    }
+}
+
 mod parse_vars {
     use crate::util::parse_vars;
     use std::env;
@@ -23,6 +70,1984 @@ mod parse_vars {
     }
 }
 
+mod privileged {
+    use crate::history::{self, Operation};
+    use crate::privileged::{is_writable, Escalation, PrivilegedOp, PrivilegedPlan};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    #[test]
+    fn escalation_follows_write_access() {
+        let dir = super::temp_dir("escalation");
+        let (open, locked) = (dir.join("open"), dir.join("locked"));
+        fs::create_dir(&open).unwrap();
+        fs::create_dir(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+
+        //Missing folders are checked through the nearest one that exists
+        assert!(is_writable(&open.join("missing/file")));
+        assert_eq!(
+            Escalation::decide(&open.join("file"), None, false),
+            Escalation::NotNeeded
+        );
+        assert_eq!(
+            Escalation::decide(&open.join("file"), Some(true), false),
+            Escalation::Required
+        );
+        assert_eq!(
+            Escalation::decide(&open.join("file"), Some(true), true),
+            Escalation::Refused
+        );
+        //Root can write anywhere, so the locked folder only needs sudo for other users
+        if unsafe { libc::geteuid() } != 0 {
+            assert!(!is_writable(&locked.join("file")));
+            assert_eq!(
+                Escalation::decide(&locked.join("file"), None, false),
+                Escalation::Required
+            );
+            assert_eq!(
+                Escalation::decide(&locked.join("file"), None, true),
+                Escalation::Refused
+            );
+        }
+
+        assert!(!Escalation::NotNeeded.check(&open).unwrap());
+        assert!(Escalation::Required.check(&open).unwrap());
+        let error = Escalation::Refused.check(&locked).unwrap_err();
+        assert!(error.to_string().contains("--no-sudo"));
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn script_stops_entry_on_failure() {
+        let dir = super::temp_dir("privileged");
+        let mut plan = PrivilegedPlan::new();
+        plan.push(
+            "first".into(),
+            vec![PrivilegedOp::CreateDir(dir.join("it's"))],
+        );
+        plan.push(
+            "second".into(),
+            vec![
+                PrivilegedOp::CreateDir(dir.join("before")),
+                PrivilegedOp::Chmod {
+                    mode: "644".into(),
+                    path: dir.join("missing"),
+                    recursive: false,
+                },
+                PrivilegedOp::CreateDir(dir.join("after")),
+            ],
+        );
+        let output = Command::new("sh")
+            .args(["-c", &plan.script().unwrap()])
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(
+            stdout.lines().collect::<Vec<_>>(),
+            ["dots-ok 0", "dots-fail 1"]
+        );
+        assert!(dir.join("it's").is_dir());
+        assert!(!dir.join("after").exists());
+    }
+
+    #[test]
+    fn rename_replaces_links_to_folders() {
+        let dir = super::temp_dir("privileged-rename");
+        let (link, temp) = (dir.join("link"), dir.join("link.tmp"));
+        fs::create_dir(dir.join("old")).unwrap();
+        std::os::unix::fs::symlink(dir.join("old"), &link).unwrap();
+        std::os::unix::fs::symlink(dir.join("new"), &temp).unwrap();
+        let mut plan = PrivilegedPlan::new();
+        plan.push(
+            "rename".into(),
+            vec![PrivilegedOp::Rename {
+                from: temp.clone(),
+                to: link.clone(),
+            }],
+        );
+        let output = Command::new("sh")
+            .args(["-c", &plan.script().unwrap()])
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "dots-ok 0\n");
+        assert_eq!(fs::read_link(&link).unwrap(), dir.join("new"));
+        assert!(temp.symlink_metadata().is_err());
+        assert!(fs::read_dir(dir.join("old")).unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn created_folders_are_recorded() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("privileged-history");
+        let deep = dir.join("etc/deep");
+        let mut plan = PrivilegedPlan::new();
+        for name in ["first", "second"] {
+            plan.push(
+                name.into(),
+                vec![
+                    PrivilegedOp::CreateDir(deep.clone()),
+                    PrivilegedOp::Symlink {
+                        source: dir.clone(),
+                        destination: deep.join(name),
+                    },
+                ],
+            );
+        }
+        history::commit().unwrap();
+
+        for (_, res) in plan.run("env").await.unwrap() {
+            res.unwrap();
+        }
+        history::commit().unwrap();
+        let operations = history::entries().unwrap().pop().unwrap().operations;
+        assert_eq!(
+            operations,
+            [
+                Operation::CreateDir {
+                    path: dir.join("etc")
+                },
+                Operation::CreateDir { path: deep.clone() },
+                Operation::Create {
+                    path: deep.join("first")
+                },
+                Operation::Create {
+                    path: deep.join("second")
+                },
+            ]
+        );
+    }
+}
+
+mod link_order {
+    use crate::actions::sync::{order_links, resolve_links, PendingLink};
+    use crate::config::ProjectConfig;
+    use crate::link::{Link, SourceFile};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn link(name: &str, destination: &str, after: Option<Vec<&str>>) -> PendingLink {
+        let mut link = Link::new(
+            name.into(),
+            destination.into(),
+            SourceFile::Source {
+                system: None,
+                src: name.into(),
+            },
+        );
+        link.after = after.map(|x| x.into_iter().map(String::from).collect());
+        PendingLink {
+            link,
+            destination: PathBuf::from(destination),
+            resolved: Ok(None),
+        }
+    }
+
+    fn names(levels: &[Vec<PendingLink>]) -> Vec<Vec<&str>> {
+        levels
+            .iter()
+            .map(|x| x.iter().map(|y| y.link.name.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn parents_before_children() {
+        let order = order_links(vec![
+            link("init", "/home/a/.config/nvim/init.lua", None),
+            link("nvim", "/home/a/.config/nvim", None),
+            link("bashrc", "/home/a/.bashrc", Some(vec!["init"])),
+        ])
+        .unwrap();
+        assert_eq!(
+            names(&order.levels),
+            vec![vec!["nvim"], vec!["init"], vec!["bashrc"]]
+        );
+    }
+
+    #[test]
+    fn overlapping_destinations() {
+        assert!(order_links(vec![
+            link("a", "/home/a/.bashrc", None),
+            link("b", "/home/a/.bashrc", None),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn links_into_one_folder() {
+        let dir = super::temp_dir("link-order");
+        let config = dir.join("home/.config");
+        fs::create_dir_all(&config).unwrap();
+        fs::create_dir(dir.join("nvim")).unwrap();
+        fs::create_dir(dir.join("kitty")).unwrap();
+        let links = ["nvim", "kitty"]
+            .iter()
+            .map(|name| link(name, config.to_str().unwrap(), None).link)
+            .collect::<Vec<_>>();
+        let mut project = ProjectConfig::new("test".into(), &dir);
+        project.links = links.clone();
+        let ctx = super::context(&dir, project);
+        let order = order_links(resolve_links(&ctx, links).unwrap()).unwrap();
+        assert_eq!(names(&order.levels), vec![vec!["nvim", "kitty"]]);
+    }
+
+    #[test]
+    fn circular_dependencies() {
+        assert!(order_links(vec![
+            link("a", "/home/a/.a", Some(vec!["b"])),
+            link("b", "/home/a/.b", Some(vec!["a"])),
+        ])
+        .is_err());
+    }
+}
+
+mod resolve {
+    use crate::actions::sync::{resolve_link, LinkState};
+    use crate::config::ProjectConfig;
+    use crate::link::{Link, SourceFile, System};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn folder_link_to_other_variant() {
+        let dir = super::temp_dir("resolve");
+        fs::create_dir_all(dir.join("nvim-laptop")).unwrap();
+        fs::create_dir_all(dir.join("nvim-desktop")).unwrap();
+        fs::create_dir_all(dir.join("home/.config")).unwrap();
+        let destination = dir.join("home/.config/nvim");
+        symlink(dir.join("nvim-desktop"), &destination).unwrap();
+        let link = Link::new(
+            "nvim".into(),
+            destination.to_str().unwrap().into(),
+            SourceFile::DynamicSource {
+                default_path: None,
+                default_system: None,
+                source_map: HashMap::from([
+                    ("laptop".parse::<System>().unwrap(), "nvim-laptop".into()),
+                    ("desktop".parse::<System>().unwrap(), "nvim-desktop".into()),
+                ]),
+            },
+        );
+        let mut ctx = super::context(&dir, ProjectConfig::new("test".into(), &dir));
+        ctx.system = Some("laptop".parse().unwrap());
+        let resolved = resolve_link(&ctx, &link).unwrap().unwrap();
+        assert_eq!(resolved.destination, destination);
+        assert_eq!(resolved.state, LinkState::Conflict);
+    }
+}
+
+mod adopt {
+    use crate::actions::adopt;
+    use crate::config::ProjectConfig;
+    use crate::link::{Link, SourceFile};
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    #[tokio::test]
+    async fn files_become_system_variants() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("adopt");
+        fs::create_dir(dir.join("home")).unwrap();
+        fs::write(dir.join("bashrc"), "shared").unwrap();
+        fs::write(dir.join("vimrc"), "shared").unwrap();
+        fs::write(dir.join("home/.bashrc"), "laptop").unwrap();
+        symlink(dir.join("vimrc"), dir.join("home/.vimrc")).unwrap();
+        let mut project = ProjectConfig::new("test".into(), &dir);
+        for (name, destination) in [("bashrc", "home/.bashrc"), ("vimrc", "home/.vimrc")] {
+            project.links.push(Link::new(
+                name.into(),
+                dir.join(destination).to_str().unwrap().into(),
+                SourceFile::Source {
+                    system: None,
+                    src: name.into(),
+                },
+            ));
+        }
+        let mut ctx = super::context(&dir, project);
+        ctx.system = Some("laptop".parse().unwrap());
+
+        let project = adopt(&ctx, true).await.unwrap();
+        let bashrc = &project.links[0].src;
+        assert_eq!(bashrc.resolve(&ctx.system).unwrap(), "bashrc.laptop");
+        assert_eq!(
+            bashrc.resolve(&Some("desktop".parse().unwrap())).unwrap(),
+            "bashrc"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("bashrc.laptop")).unwrap(),
+            "laptop"
+        );
+        assert_eq!(
+            fs::canonicalize(dir.join("home/.bashrc")).unwrap(),
+            dir.join("bashrc.laptop")
+        );
+        //Links that are already deployed are left alone
+        assert_eq!(project.links[1].src.resolve(&ctx.system).unwrap(), "vimrc");
+        assert!(!dir.join("vimrc.laptop").exists());
+    }
+}
+
+mod add {
+    use crate::actions::add;
+    use crate::config::ProjectConfig;
+    use crate::link::{Link, LinkStyle, SourceFile};
+    use crate::Args;
+    use clap::Parser;
+    use std::fs;
+    use std::path::Path;
+
+    fn names(project: &ProjectConfig) -> Vec<&str> {
+        project.links.iter().map(|x| x.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn globs_leave_out_excluded_files() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("add-glob");
+        let config = dir.join("home/.config");
+        fs::create_dir_all(&config).unwrap();
+        for name in ["a.conf", "b.conf", "c.log"] {
+            fs::write(config.join(name), name).unwrap();
+        }
+        let ctx = super::context(&dir, ProjectConfig::new("test".into(), &dir));
+
+        let project = add(
+            &ctx,
+            vec![config.join("*").to_str().unwrap().into()],
+            Some("config".into()),
+            None,
+            false,
+            vec!["*.log".into()],
+        )
+        .await
+        .unwrap();
+        assert_eq!(names(&project), ["a.conf", "b.conf"]);
+        assert_eq!(
+            fs::read_link(config.join("a.conf")).unwrap(),
+            dir.join("config/a.conf")
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("config/b.conf")).unwrap(),
+            "b.conf"
+        );
+        assert!(config.join("c.log").is_file());
+        assert!(!dir.join("config/c.log").exists());
+    }
+
+    #[tokio::test]
+    async fn recursive_links_every_file() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("add-recursive");
+        let nvim = dir.join("home/nvim");
+        fs::create_dir_all(nvim.join("lua")).unwrap();
+        fs::write(nvim.join("init.lua"), "init").unwrap();
+        fs::write(nvim.join("init.lua.bak"), "old").unwrap();
+        fs::write(nvim.join("lua/plugins.lua"), "plugins").unwrap();
+        let ctx = super::context(&dir, ProjectConfig::new("test".into(), &dir));
+
+        let mut project = add(
+            &ctx,
+            vec![nvim.to_str().unwrap().into()],
+            None,
+            None,
+            true,
+            vec!["*.bak".into()],
+        )
+        .await
+        .unwrap();
+        project.links.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(names(&project), ["nvim/init.lua", "nvim/lua/plugins.lua"]);
+        assert_eq!(
+            fs::read_link(nvim.join("lua/plugins.lua")).unwrap(),
+            dir.join("nvim/lua/plugins.lua")
+        );
+        assert!(nvim.join("init.lua.bak").is_file());
+        assert!(!dir.join("nvim/init.lua.bak").exists());
+    }
+
+    #[tokio::test]
+    async fn failures_put_moved_files_back() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("add-rollback");
+        let home = dir.join("home");
+        fs::create_dir_all(home.join("z")).unwrap();
+        fs::write(home.join("a"), "a").unwrap();
+        fs::write(home.join("z/x"), "x").unwrap();
+        //The folder z has to go where the project has a file
+        fs::create_dir(dir.join("dest")).unwrap();
+        fs::write(dir.join("dest/z"), "").unwrap();
+        let ctx = super::context(&dir, ProjectConfig::new("test".into(), &dir));
+
+        let result = add(
+            &ctx,
+            vec![home.join("*").to_str().unwrap().into()],
+            Some("dest".into()),
+            None,
+            true,
+            vec![],
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(!home.join("a").symlink_metadata().unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(home.join("a")).unwrap(), "a");
+        assert!(!dir.join("dest/a").exists());
+        assert_eq!(fs::read_to_string(home.join("z/x")).unwrap(), "x");
+    }
+
+    #[tokio::test]
+    async fn new_variants_keep_the_link_style() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("add-style");
+        fs::create_dir(dir.join("home")).unwrap();
+        fs::write(dir.join("bashrc"), "shared").unwrap();
+        fs::write(dir.join("home/.bashrc"), "desktop").unwrap();
+        let destination = dir.join("home/.bashrc");
+        let mut project = ProjectConfig::new("test".into(), &dir);
+        let mut link = Link::new(
+            "bashrc".into(),
+            destination.to_str().unwrap().into(),
+            SourceFile::Source {
+                system: None,
+                src: "bashrc".into(),
+            },
+        );
+        link.link_style = Some(LinkStyle::Relative);
+        project.links.push(link);
+        let mut ctx = super::context(&dir, project);
+        ctx.args = Args::try_parse_from(["dots", "--system", "desktop", "list"]).unwrap();
+
+        let project = add(
+            &ctx,
+            vec![dir.join("home/.bash*").to_str().unwrap().into()],
+            Some("desktop".into()),
+            None,
+            false,
+            vec![],
+        )
+        .await
+        .unwrap();
+        assert_eq!(project.links.len(), 1);
+        assert_eq!(
+            fs::read_link(&destination).unwrap(),
+            Path::new("../desktop/.bashrc")
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("desktop/.bashrc")).unwrap(),
+            "desktop"
+        );
+    }
+}
+
+mod prune {
+    use crate::actions::prune;
+    use crate::config::ProjectConfig;
+    use crate::link::{Link, SourceFile};
+    use crate::state::ProjectState;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    #[tokio::test]
+    async fn only_dangling_project_links_are_removed() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("prune");
+        let home = dir.join("home");
+        fs::create_dir_all(home.join("deep")).unwrap();
+        fs::write(dir.join("kept"), "").unwrap();
+        symlink(dir.join("gone"), home.join("gone")).unwrap();
+        fs::write(home.join("edited"), "").unwrap();
+        symlink("/nonexistent/foreign", home.join("foreign")).unwrap();
+        symlink(dir.join("kept"), home.join("kept")).unwrap();
+        symlink(dir.join("nested"), home.join("deep/nested")).unwrap();
+        let mut project = ProjectConfig::new("test".into(), &dir);
+        for name in ["gone", "edited", "foreign", "kept", "deep/nested"] {
+            project.links.push(Link::new(
+                name.trim_start_matches("deep/").into(),
+                home.join(name).to_str().unwrap().into(),
+                SourceFile::Source {
+                    system: None,
+                    src: name.trim_start_matches("deep/").into(),
+                },
+            ));
+        }
+        //Escalated through a sudo program that runs the commands as they are
+        project.links[4].sudo_required = Some(true);
+        let mut state = ProjectState::default();
+        state.record_dirs([home.join("deep")]);
+        state.save(&project.id).unwrap();
+        let mut ctx = super::context(&dir, project);
+        ctx.system_config.sudo_program = Some("env".into());
+
+        let unchanged = prune(&ctx, true, true).await.unwrap();
+        assert_eq!(unchanged.links.len(), 5);
+        assert!(home.join("gone").symlink_metadata().is_ok());
+
+        let project = prune(&ctx, false, true).await.unwrap();
+        let names: Vec<&str> = project.links.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["kept"]);
+        assert!(home.join("gone").symlink_metadata().is_err());
+        assert!(home.join("edited").is_file());
+        assert!(home.join("foreign").symlink_metadata().is_ok());
+        assert!(home.join("kept").exists());
+        assert!(!home.join("deep").exists());
+        let state = ProjectState::load(&ctx.project.id).unwrap();
+        assert!(state.created_dirs.is_empty());
+    }
+}
+
+mod unlink {
+    use crate::actions::unlink;
+    use crate::config::ProjectConfig;
+    use crate::link::{Link, SourceFile};
+    use crate::state::ProjectState;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    #[tokio::test]
+    async fn escalated_unlink_removes_emptied_folders() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("unlink");
+        let deep = dir.join("home/deep");
+        fs::create_dir_all(&deep).unwrap();
+        fs::write(dir.join("conf"), "").unwrap();
+        symlink(dir.join("conf"), deep.join("conf")).unwrap();
+        let mut project = ProjectConfig::new("test".into(), &dir);
+        let mut link = Link::new(
+            "conf".into(),
+            deep.join("conf").to_str().unwrap().into(),
+            SourceFile::Source {
+                system: None,
+                src: "conf".into(),
+            },
+        );
+        link.sudo_required = Some(true);
+        project.links.push(link);
+        let mut state = ProjectState::default();
+        state.record_dirs([deep.clone()]);
+        state.save(&project.id).unwrap();
+        let mut ctx = super::context(&dir, project);
+        ctx.system_config.sudo_program = Some("env".into());
+
+        unlink(&ctx, &ctx.project.links.clone()).await.unwrap();
+        assert!(!deep.exists());
+        assert!(dir.join("home").is_dir());
+        assert!(dir.join("conf").is_file());
+        let state = ProjectState::load(&ctx.project.id).unwrap();
+        assert!(state.created_dirs.is_empty());
+    }
+}
+
+mod goal {
+    use crate::actions::goal::{goals, GoalSubCommand};
+    use crate::actions::sync::sync;
+    use crate::config::ProjectConfig;
+    use crate::goals::Goal;
+    use crate::link::{Link, SourceFile};
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[tokio::test]
+    async fn disabled_goals_are_still_synced() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("goal");
+        let home = dir.join("home");
+        fs::create_dir(&home).unwrap();
+        let mut project = ProjectConfig::new("test".into(), &dir);
+        for name in ["bashrc", "sway"] {
+            fs::write(dir.join(name), name).unwrap();
+            project.links.push(Link::new(
+                name.into(),
+                home.join(name).to_str().unwrap().into(),
+                SourceFile::Source {
+                    system: None,
+                    src: name.into(),
+                },
+            ));
+        }
+        let mut gui = Goal::new(Vec::new());
+        gui.enabled = false;
+        gui.links.push("sway".into());
+        project.goals = Some(HashMap::from([("gui".to_string(), gui)]));
+        let mut ctx = super::context(&dir, project);
+
+        let command = GoalSubCommand::AddFile {
+            goal: "gui".into(),
+            files: vec![dir.join("bashrc")],
+        };
+        ctx.project = goals(&ctx, command).await.unwrap();
+        assert_eq!(
+            ctx.project.goals.as_ref().unwrap()["gui"].links,
+            ["sway", "bashrc"]
+        );
+
+        sync(ctx, None, false, false, None).await.unwrap();
+        assert_eq!(
+            fs::read_link(home.join("bashrc")).unwrap(),
+            dir.join("bashrc")
+        );
+        assert_eq!(fs::read_link(home.join("sway")).unwrap(), dir.join("sway"));
+    }
+}
+
+mod revert {
+    use crate::actions::{eject, revert, RevertTarget};
+    use crate::config::ProjectConfig;
+    use crate::goals::Goal;
+    use crate::link::{Link, SourceFile};
+    use crate::ProjectContext;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::{Path, PathBuf};
+
+    /// A project with the linked files bashrc and vimrc, where the goal editor has vimrc
+    fn linked(name: &str) -> (PathBuf, ProjectContext) {
+        let dir = super::temp_dir(name);
+        let home = dir.join("home");
+        fs::create_dir(&home).unwrap();
+        let mut project = ProjectConfig::new("test".into(), &dir);
+        for name in ["bashrc", "vimrc"] {
+            fs::write(dir.join(name), name).unwrap();
+            symlink(dir.join(name), home.join(name)).unwrap();
+            project.links.push(Link::new(
+                name.into(),
+                home.join(name).to_str().unwrap().into(),
+                SourceFile::Source {
+                    system: None,
+                    src: name.into(),
+                },
+            ));
+        }
+        let mut editor = Goal::new(Vec::new());
+        editor.links.push("vimrc".into());
+        project.goals = Some(HashMap::from([("editor".to_string(), editor)]));
+        (dir.clone(), super::context(&dir, project))
+    }
+
+    fn is_copy(path: &Path, text: &str) -> bool {
+        !path.symlink_metadata().unwrap().is_symlink() && fs::read_to_string(path).unwrap() == text
+    }
+
+    #[tokio::test]
+    async fn by_link() {
+        let _lock = super::lock_data().await;
+        let (dir, ctx) = linked("revert-link");
+
+        let project = revert(&ctx, RevertTarget::Link("bashrc".into()))
+            .await
+            .unwrap();
+        assert!(is_copy(&dir.join("home/bashrc"), "bashrc"));
+        assert!(!dir.join("bashrc").exists());
+        let names: Vec<&str> = project.links.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["vimrc"]);
+        assert!(fs::read_link(dir.join("home/vimrc")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn by_goal() {
+        let _lock = super::lock_data().await;
+        let (dir, ctx) = linked("revert-goal");
+
+        let project = revert(&ctx, RevertTarget::Goal("editor".into()))
+            .await
+            .unwrap();
+        assert!(is_copy(&dir.join("home/vimrc"), "vimrc"));
+        assert!(!dir.join("vimrc").exists());
+        let names: Vec<&str> = project.links.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["bashrc"]);
+        assert!(fs::read_link(dir.join("home/bashrc")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn eject_removes_the_config() {
+        let _lock = super::lock_data().await;
+        let (dir, ctx) = linked("eject");
+        ctx.project.save_to(&dir).unwrap();
+
+        eject(&ctx, true).await.unwrap();
+        assert!(is_copy(&dir.join("home/bashrc"), "bashrc"));
+        assert!(is_copy(&dir.join("home/vimrc"), "vimrc"));
+        assert!(!dir.join(".links.toml").exists());
+        //The project files themselves are kept
+        assert!(dir.join("bashrc").is_file());
+    }
+}
+
+mod untracked {
+    use crate::actions::untracked::find_untracked;
+    use crate::config::ProjectConfig;
+    use crate::link::{Link, SourceFile};
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn skips_sources_built_ins_and_ignored_files() {
+        let dir = super::temp_dir("untracked");
+        for path in [
+            ".git/config",
+            ".links.toml",
+            "bashrc",
+            "bashrc.laptop",
+            "nvim/init.lua",
+            "nvim/lua/plugins.lua",
+            "scratch.swp",
+            "build/out",
+            "notes.txt",
+            "extra/stray",
+        ] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        fs::write(dir.join(".dotsignore"), "*.swp\nbuild/\n").unwrap();
+        let mut project = ProjectConfig::new("test".into(), &dir);
+        for name in ["bashrc", "nvim"] {
+            project.links.push(Link::new(
+                name.into(),
+                format!("/home/user/.{}", name).into(),
+                SourceFile::Source {
+                    system: None,
+                    src: format!("./{}", name),
+                },
+            ));
+        }
+        project.links[0].src = project.links[0]
+            .src
+            .clone()
+            .insert_link(&"laptop".parse().unwrap(), "bashrc.laptop")
+            .unwrap();
+        let ctx = super::context(&dir, project);
+
+        assert_eq!(
+            find_untracked(&ctx).unwrap(),
+            [PathBuf::from("extra/stray"), PathBuf::from("notes.txt")]
+        );
+    }
+}
+
+mod relocate {
+    use crate::actions::relocate;
+    use crate::config::ProjectConfig;
+    use crate::link::{Link, SourceFile};
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn links_follow_the_project() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("relocate");
+        let (old, new, home) = (dir.join("dots"), dir.join("moved"), dir.join("home"));
+        fs::create_dir(&old).unwrap();
+        fs::create_dir(&home).unwrap();
+        let mut project = ProjectConfig::new("test".into(), &old);
+        for name in ["bashrc", "vimrc"] {
+            fs::write(old.join(name), name).unwrap();
+            project.links.push(Link::new(
+                name.into(),
+                home.join(name).to_str().unwrap().into(),
+                SourceFile::Source {
+                    system: None,
+                    src: name.into(),
+                },
+            ));
+        }
+        symlink(old.join("bashrc"), home.join("bashrc")).unwrap();
+        symlink("../dots/vimrc", home.join("vimrc")).unwrap();
+        let mut ctx = super::context(&old, project);
+        ctx.system_config.add_project(&ctx.project, old.clone());
+        ctx.system_config.default = Some(old.clone());
+
+        let system_config = relocate(&ctx, &new).await.unwrap();
+        assert!(!old.exists());
+        assert_eq!(fs::read_to_string(new.join("bashrc")).unwrap(), "bashrc");
+        assert_eq!(
+            fs::read_link(home.join("bashrc")).unwrap(),
+            new.join("bashrc")
+        );
+        assert_eq!(
+            fs::read_link(home.join("vimrc")).unwrap(),
+            Path::new("../moved/vimrc")
+        );
+        assert_eq!(system_config.get_project(&ctx.project).unwrap().path, new);
+        assert_eq!(system_config.default, Some(new));
+    }
+}
+
+mod projects {
+    use crate::actions::{projects, ProjectsSubCommand};
+    use crate::config::{ProjectConfig, ProjectOutput, SystemConfig};
+    use crate::util::WritableConfig;
+    use std::path::PathBuf;
+
+    #[test]
+    fn registered_by_id() {
+        let (first, second) = (
+            ProjectConfig::new("dots".into(), &PathBuf::from("/first")),
+            ProjectConfig::new("dots".into(), &PathBuf::from("/second")),
+        );
+        let mut system_config = SystemConfig::new();
+        //Registered by name before projects had ids
+        system_config.projects.insert(
+            "dots".into(),
+            ProjectOutput {
+                name: None,
+                system: Some("laptop".parse().unwrap()),
+                path: "/first".into(),
+            },
+        );
+        assert_eq!(
+            system_config.get_project(&first).unwrap().path,
+            PathBuf::from("/first")
+        );
+
+        system_config.add_project(&first, "/first".into());
+        system_config.add_project(&second, "/second".into());
+        assert_eq!(system_config.projects.len(), 2);
+        assert!(!system_config.projects.contains_key("dots"));
+        let output = system_config.get_project(&first).unwrap();
+        assert_eq!(output.system, Some("laptop".parse().unwrap()));
+        assert_eq!(
+            system_config.get_project(&second).unwrap().path,
+            PathBuf::from("/second")
+        );
+        assert_eq!(system_config.find_project(&second.id).unwrap().0, second.id);
+        assert!(system_config.find_project("dots").is_err());
+        assert!(system_config.find_project("missing").is_err());
+    }
+
+    #[tokio::test]
+    async fn rename_remove_and_set_default() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("projects");
+        let project = ProjectConfig::new("dots".into(), &dir);
+        project.save_to(&dir).unwrap();
+        let mut system_config = SystemConfig::new();
+        system_config.projects.insert(
+            "dots".into(),
+            ProjectOutput {
+                name: None,
+                system: None,
+                path: dir.clone(),
+            },
+        );
+
+        let rename = ProjectsSubCommand::Rename {
+            project: "dots".into(),
+            name: "home".into(),
+        };
+        let system_config = projects(&system_config, rename).unwrap().unwrap();
+        let (key, output) = system_config.find_project("home").unwrap();
+        assert_eq!(key, project.id);
+        assert_eq!(output.name.as_deref(), Some("home"));
+        let saved = ProjectConfig::read_from_file(&dir.join(".links.toml")).unwrap();
+        assert_eq!(saved.name, "home");
+
+        let set_default = ProjectsSubCommand::SetDefault {
+            project: "home".into(),
+        };
+        let system_config = projects(&system_config, set_default).unwrap().unwrap();
+        assert_eq!(system_config.default, Some(dir.clone()));
+
+        let remove = ProjectsSubCommand::Remove {
+            project: project.id.clone(),
+        };
+        let system_config = projects(&system_config, remove).unwrap().unwrap();
+        assert!(system_config.projects.is_empty());
+        assert_eq!(system_config.default, None);
+        assert!(dir.join(".links.toml").exists());
+    }
+}
+
+mod settings {
+    use crate::actions::{project_settings, system_settings, ConfigSubCommand};
+    use crate::config::{ProjectConfig, SystemConfig};
+
+    fn set(key: &str, value: &str) -> ConfigSubCommand {
+        ConfigSubCommand::Set {
+            key: key.into(),
+            value: value.into(),
+            global: false,
+        }
+    }
+
+    #[test]
+    fn project_values_are_checked() {
+        let project = ProjectConfig::new("dots".into(), &std::env::temp_dir());
+        //Any system can be the default until the project lists its systems
+        let project = project_settings(&project, set("default", "laptop"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(project.default, Some("laptop".parse().unwrap()));
+        assert!(project_settings(&project, set("systems", "desktop")).is_err());
+        let project = project_settings(&project, set("systems", "laptop, desktop"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(project.systems.len(), 2);
+        assert!(project_settings(&project, set("default", "server")).is_err());
+
+        let project = project_settings(&project, set("variables.CONFIG", "~/.config"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(project.variables.as_ref().unwrap()["CONFIG"], "~/.config");
+        assert!(project_settings(&project, set("variables.a-b", "x")).is_err());
+        assert!(project_settings(&project, set("link_style", "hard")).is_err());
+        assert!(project_settings(&project, set("name", " ")).is_err());
+        assert!(project_settings(&project, set("colour", "red")).is_err());
+    }
+
+    #[test]
+    fn system_values_are_checked() {
+        let dir = super::temp_dir("settings");
+        let system = SystemConfig::new();
+        let system = system_settings(&system, set("backups.keep_last", "3"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(system.backups.as_ref().unwrap().keep_last, Some(3));
+        assert!(system_settings(&system, set("backups.keep_last", "all")).is_err());
+        assert!(system_settings(&system, set("sudo_program", "dots-no-such-sudo")).is_err());
+        assert!(system_settings(&system, set("sudo_program", "env")).is_ok());
+        //Only folders with a project config can be the default
+        let default = set("default", dir.to_str().unwrap());
+        assert!(system_settings(&system, default.clone()).is_err());
+        std::fs::write(dir.join(".links.toml"), "").unwrap();
+        let system = system_settings(&system, default).unwrap().unwrap();
+        assert_eq!(system.default, Some(dir));
+
+        let unset = ConfigSubCommand::Unset {
+            key: "backups.keep_last".into(),
+            global: true,
+        };
+        let system = system_settings(&system, unset).unwrap().unwrap();
+        assert!(system.backups.is_none());
+    }
+}
+
+mod backups {
+    use crate::actions::{backups, undo, BackupsSubCommand};
+    use crate::backup::BackupStore;
+    use crate::config::{BackupRetention, SystemConfig};
+    use crate::history::{self, Operation};
+    use std::ffi::CString;
+    use std::fs;
+    use std::os::unix::{
+        ffi::OsStrExt,
+        fs::{symlink, PermissionsExt},
+    };
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn restore_folder() {
+        let dir = super::temp_dir("backups-restore");
+        let store = BackupStore::at(dir.join("store")).unwrap();
+        let original = dir.join("nvim");
+        fs::create_dir_all(original.join("lua")).unwrap();
+        fs::write(original.join("lua/init.lua"), "init").unwrap();
+        fs::set_permissions(
+            original.join("lua/init.lua"),
+            fs::Permissions::from_mode(0o600),
+        )
+        .unwrap();
+        let time = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        fs::File::options()
+            .write(true)
+            .open(original.join("lua/init.lua"))
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+        let (name, value) = (CString::new("user.dots").unwrap(), b"kept");
+        let path = CString::new(original.join("lua/init.lua").as_os_str().as_bytes()).unwrap();
+        //Some filesystems don't take user attributes, then only the rest is checked
+        let xattrs = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const _,
+                value.len(),
+                0,
+            )
+        } == 0;
+        symlink("lua/init.lua", original.join("init.lua")).unwrap();
+        fs::File::open(original.join("lua"))
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+        let id = store.snapshot(&original, "test").unwrap().unwrap();
+        assert!(store
+            .snapshot(&dir.join("missing"), "test")
+            .unwrap()
+            .is_none());
+
+        let restored = dir.join("restored");
+        store.restore(&store.get(&id).unwrap(), &restored).unwrap();
+        assert_eq!(
+            fs::read_to_string(restored.join("lua/init.lua")).unwrap(),
+            "init"
+        );
+        let mode = fs::metadata(restored.join("lua/init.lua"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(
+            fs::metadata(restored.join("lua/init.lua"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            time
+        );
+        assert_eq!(
+            fs::metadata(restored.join("lua"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            time
+        );
+        if xattrs {
+            let path = CString::new(restored.join("lua/init.lua").as_os_str().as_bytes()).unwrap();
+            let mut buf = [0u8; 16];
+            let size = unsafe {
+                libc::getxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    buf.as_mut_ptr() as *mut _,
+                    buf.len(),
+                )
+            };
+            assert_eq!(&buf[..size.max(0) as usize], value);
+        }
+        assert_eq!(
+            fs::read_link(restored.join("init.lua")).unwrap(),
+            std::path::Path::new("lua/init.lua")
+        );
+        //Never over something that exists
+        assert!(store.restore(&store.get(&id).unwrap(), &original).is_err());
+    }
+
+    #[test]
+    fn gc_keeps_newest_of_each_path() {
+        let dir = super::temp_dir("backups-gc");
+        let store = BackupStore::at(dir.join("store")).unwrap();
+        let (bashrc, vimrc) = (dir.join("bashrc"), dir.join("vimrc"));
+        let mut ids = Vec::new();
+        for text in ["one", "two", "three"] {
+            fs::write(&bashrc, text).unwrap();
+            ids.push(store.snapshot(&bashrc, "test").unwrap().unwrap());
+        }
+        fs::write(&vimrc, "vim").unwrap();
+        store.snapshot(&vimrc, "test").unwrap();
+
+        //Nothing has expired yet
+        assert_eq!(store.gc(&BackupRetention::default()).unwrap(), (0, 0));
+        let retention = BackupRetention {
+            max_age_days: Some(0),
+            keep_last: Some(1),
+        };
+        assert_eq!(store.gc(&retention).unwrap(), (2, 2));
+        let left: Vec<_> = store.list().unwrap().into_iter().map(|x| x.id).collect();
+        assert_eq!(left.len(), 2);
+        assert!(left.contains(&ids[2]));
+        fs::remove_file(&bashrc).unwrap();
+        store
+            .restore(&store.get(&ids[2]).unwrap(), &bashrc)
+            .unwrap();
+        assert_eq!(fs::read_to_string(&bashrc).unwrap(), "three");
+    }
+
+    #[tokio::test]
+    async fn forced_restore_can_be_undone() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("backups-force");
+        let bashrc = dir.join("bashrc");
+        fs::write(&bashrc, "old").unwrap();
+        let id = BackupStore::open()
+            .unwrap()
+            .snapshot(&bashrc, "test")
+            .unwrap()
+            .unwrap();
+        fs::write(&bashrc, "new").unwrap();
+        history::commit().unwrap();
+
+        let restore = BackupsSubCommand::Restore {
+            id,
+            to: None,
+            force: true,
+        };
+        backups(&SystemConfig::new(), restore).unwrap();
+        history::commit().unwrap();
+        assert_eq!(fs::read_to_string(&bashrc).unwrap(), "old");
+        let operations = history::entries().unwrap().pop().unwrap().operations;
+        assert!(matches!(
+            &operations[..],
+            [Operation::Backup { path, .. }, Operation::Create { path: created }]
+                if path == &bashrc && created == &bashrc
+        ));
+
+        undo(&SystemConfig::new(), false, 1, true).await.unwrap();
+        assert_eq!(fs::read_to_string(&bashrc).unwrap(), "new");
+    }
+}
+
+mod journal {
+    use crate::config::get_data_loc;
+    use crate::journal::{self, MoveStage, PendingMove};
+    use crate::link::LinkStyle;
+    use std::fs;
+    use std::path::Path;
+
+    fn pending(dir: &Path, stage: MoveStage) -> PendingMove {
+        PendingMove {
+            original: dir.join("home/nvim"),
+            destination: dir.join("project/nvim"),
+            temp: dir.join("project/.nvim.tmp"),
+            stage,
+            style: LinkStyle::default(),
+        }
+    }
+
+    /// A folder being moved, with only some of its files at `at`
+    fn partial(at: &Path) {
+        fs::create_dir_all(at).unwrap();
+        fs::write(at.join("init.lua"), "init").unwrap();
+    }
+
+    fn journaled(original: &Path) -> bool {
+        let journal = get_data_loc().unwrap().join("journal.toml");
+        fs::read_to_string(journal)
+            .map(|x| x.contains(original.to_str().unwrap()))
+            .unwrap_or(false)
+    }
+
+    #[tokio::test]
+    async fn copy_rolls_back() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("journal-moving");
+        let pending = pending(&dir, MoveStage::Moving);
+        partial(&pending.original);
+        fs::write(pending.original.join("plugins.lua"), "plugins").unwrap();
+        partial(&pending.temp);
+        journal::record(&pending).unwrap();
+        assert!(journaled(&pending.original));
+
+        journal::recover().unwrap();
+        assert!(!pending.temp.exists());
+        assert!(!pending.destination.exists());
+        assert!(pending.original.join("plugins.lua").is_file());
+        assert!(!journaled(&pending.original));
+    }
+
+    #[tokio::test]
+    async fn removal_moves_forward() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("journal-moved");
+        let pending = pending(&dir, MoveStage::Moved);
+        partial(&pending.destination);
+        fs::write(pending.destination.join("plugins.lua"), "plugins").unwrap();
+        partial(&pending.original);
+        journal::record(&pending).unwrap();
+
+        journal::recover().unwrap();
+        assert_eq!(
+            fs::read_link(&pending.original).unwrap(),
+            pending.destination
+        );
+        assert!(pending.original.join("plugins.lua").is_file());
+        assert!(!journaled(&pending.original));
+    }
+}
+
+mod copy {
+    use crate::file_actions::recurse_copy;
+    use std::ffi::CString;
+    use std::fs;
+    use std::os::unix::{
+        ffi::OsStrExt,
+        fs::{symlink, MetadataExt, PermissionsExt},
+    };
+    use std::path::Path;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn c_path(path: &Path) -> CString {
+        CString::new(path.as_os_str().as_bytes()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn keeps_attributes() {
+        let dir = super::temp_dir("copy");
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("lua")).unwrap();
+        let file = src.join("lua/init.lua");
+        fs::write(&file, "init").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        let time = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+        let (name, value) = (CString::new("user.dots").unwrap(), b"kept");
+        //Some filesystems don't take user attributes, then only the rest is checked
+        let xattrs = unsafe {
+            libc::setxattr(
+                c_path(&file).as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const _,
+                value.len(),
+                0,
+            )
+        } == 0;
+        symlink("lua/init.lua", src.join("init.lua")).unwrap();
+        fs::set_permissions(src.join("lua"), fs::Permissions::from_mode(0o750)).unwrap();
+
+        let dest = dir.join("dest");
+        recurse_copy(&src, &dest).await.unwrap();
+        let copied = dest.join("lua/init.lua");
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "init");
+        let metadata = fs::metadata(&copied).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o640);
+        assert_eq!(metadata.modified().unwrap(), time);
+        assert_eq!(
+            fs::metadata(dest.join("lua")).unwrap().mode() & 0o7777,
+            0o750
+        );
+        assert_eq!(
+            fs::read_link(dest.join("init.lua")).unwrap(),
+            Path::new("lua/init.lua")
+        );
+        if xattrs {
+            let mut buf = [0u8; 16];
+            let size = unsafe {
+                libc::getxattr(
+                    c_path(&copied).as_ptr(),
+                    name.as_ptr(),
+                    buf.as_mut_ptr() as *mut _,
+                    buf.len(),
+                )
+            };
+            assert_eq!(&buf[..size.max(0) as usize], value);
+        }
+    }
+}
+
+mod scan {
+    use crate::actions::scan::{propose, Layout};
+    use crate::ignore::IgnoreRules;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// Sources and destinations of the links proposed for `root`, linked into `home`
+    fn proposed(root: &Path, home: &Path, layout: Layout) -> Vec<(PathBuf, PathBuf)> {
+        let ignore = IgnoreRules::parse("*.swp").unwrap().with_root(root);
+        let mut output: Vec<_> = propose(root, home, layout, &ignore)
+            .unwrap()
+            .into_iter()
+            .map(|x| (x.src, x.destination))
+            .collect();
+        output.sort();
+        output
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(PathBuf, PathBuf)> {
+        pairs
+            .iter()
+            .map(|(src, dest)| (src.into(), dest.into()))
+            .collect()
+    }
+
+    #[test]
+    fn home_folder() {
+        let temp = super::temp_dir("scan-home");
+        let (dir, home) = (temp.join("dots"), temp.join("user"));
+        fs::create_dir_all(dir.join("home/.config/nvim")).unwrap();
+        fs::create_dir_all(dir.join("home/.config/kitty")).unwrap();
+        fs::write(dir.join("home/.config/nvim/init.lua"), "").unwrap();
+        fs::create_dir_all(dir.join("home/.local/bin")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("home/.bashrc"), "").unwrap();
+        fs::write(dir.join("home/.bashrc.swp"), "").unwrap();
+        fs::write(dir.join("README.md"), "").unwrap();
+        assert_eq!(
+            proposed(&dir, &home, Layout::Home),
+            pairs(&[
+                ("home/.bashrc", ".bashrc"),
+                ("home/.config", ".config"),
+                ("home/.local", ".local"),
+            ])
+        );
+        //Folders already in the home folder are linked by what is inside them
+        fs::create_dir_all(home.join(".config/nvim")).unwrap();
+        fs::create_dir_all(home.join(".local")).unwrap();
+        std::os::unix::fs::symlink(temp.join("elsewhere"), home.join(".local/bin")).unwrap();
+        assert_eq!(
+            proposed(&dir, &home, Layout::Home),
+            pairs(&[
+                ("home/.bashrc", ".bashrc"),
+                ("home/.config/kitty", ".config/kitty"),
+                ("home/.config/nvim/init.lua", ".config/nvim/init.lua"),
+                ("home/.local/bin", ".local/bin"),
+            ])
+        );
+        fs::remove_dir_all(dir.join("home")).unwrap();
+        assert_eq!(
+            proposed(&dir, &home, Layout::Home),
+            pairs(&[("README.md", "README.md")])
+        );
+    }
+
+    #[test]
+    fn stow_packages_fold() {
+        let dir = super::temp_dir("scan-stow");
+        fs::create_dir_all(dir.join("nvim/.config/nvim")).unwrap();
+        fs::create_dir_all(dir.join("kitty/.config/kitty")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::create_dir(dir.join("bash")).unwrap();
+        fs::write(dir.join("bash/dot-bashrc"), "").unwrap();
+        fs::write(dir.join("README.md"), "").unwrap();
+        assert_eq!(
+            proposed(&dir, &dir.join("user"), Layout::Stow),
+            pairs(&[
+                ("bash/dot-bashrc", ".bashrc"),
+                ("kitty/.config/kitty", ".config/kitty"),
+                ("nvim/.config/nvim", ".config/nvim"),
+            ])
+        );
+        //A package's folder is linked into a folder the home folder already has
+        fs::create_dir_all(dir.join("user/.config/nvim")).unwrap();
+        fs::write(dir.join("nvim/.config/nvim/init.lua"), "").unwrap();
+        assert_eq!(
+            proposed(&dir, &dir.join("user"), Layout::Stow),
+            pairs(&[
+                ("bash/dot-bashrc", ".bashrc"),
+                ("kitty/.config/kitty", ".config/kitty"),
+                ("nvim/.config/nvim/init.lua", ".config/nvim/init.lua"),
+            ])
+        );
+        //Files can't be folded, so two packages can't both have them
+        fs::write(dir.join("kitty/dot-bashrc"), "").unwrap();
+        let ignore = IgnoreRules::parse("").unwrap();
+        assert!(propose(&dir, &dir.join("user"), Layout::Stow, &ignore).is_err());
+    }
+
+    #[test]
+    fn flat_files() {
+        let dir = super::temp_dir("scan-flat");
+        fs::create_dir(dir.join("scripts")).unwrap();
+        for name in ["bashrc", ".vimrc", ".links.toml"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        assert_eq!(
+            proposed(&dir, &dir.join("user"), Layout::Flat),
+            pairs(&[(".vimrc", ".vimrc"), ("bashrc", ".bashrc")])
+        );
+    }
+}
+
+mod import {
+    use crate::actions::{import, ImportSource};
+    use crate::config::ProjectConfig;
+    use std::fs;
+    use std::path::Path;
+
+    /// Each link as `name: destination <- sources`, with systems before the source they use
+    fn links(project: &ProjectConfig) -> Vec<String> {
+        project
+            .links
+            .iter()
+            .map(|link| {
+                let mut sources: Vec<_> = link
+                    .src
+                    .clone()
+                    .into_iter()
+                    .map(|(_, system, src)| match system {
+                        Some(system) => format!("{}={}", system, src),
+                        None => src,
+                    })
+                    .collect();
+                sources.sort();
+                format!(
+                    "{}: {} <- {}",
+                    link.name,
+                    link.destination,
+                    sources.join(" ")
+                )
+            })
+            .collect()
+    }
+
+    fn perms(project: &ProjectConfig, name: &str) -> Option<String> {
+        let link = project.links.iter().find(|x| x.name == name).unwrap();
+        link.perms.as_ref().and_then(|x| x.user_code.clone())
+    }
+
+    fn write(root: &Path, files: &[&str]) {
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+    }
+
+    #[test]
+    fn stow_packages_become_goals() {
+        let temp = super::temp_dir("import-stow");
+        let (dir, home) = (temp.join("dotfiles"), temp.join("user"));
+        fs::create_dir_all(home.join(".config")).unwrap();
+        write(
+            &dir,
+            &[
+                "bash/dot-bashrc",
+                "nvim/.config/nvim/init.lua",
+                "nvim/.stow-local-ignore",
+            ],
+        );
+        let project = import(&dir, &home, ImportSource::Stow).unwrap();
+        assert_eq!(project.name, "dotfiles");
+        //Like stow, the existing ~/.config is linked into rather than replaced
+        assert_eq!(
+            links(&project),
+            [
+                ".bashrc: $HOME/.bashrc <- bash/dot-bashrc",
+                "nvim: $HOME/.config/nvim <- nvim/.config/nvim",
+            ]
+        );
+        let goals = project.goals.unwrap();
+        assert_eq!(goals["bash"].links, [".bashrc"]);
+        assert_eq!(goals["nvim"].links, ["nvim"]);
+    }
+
+    #[test]
+    fn chezmoi_names_and_data() {
+        let dir = super::temp_dir("import-chezmoi");
+        write(
+            &dir,
+            &[
+                "dot_bashrc",
+                "dot_zshrc.tmpl",
+                "private_dot_ssh/config",
+                "dot_config/executable_script.sh",
+                "dot_config/kitty/kitty.conf",
+                ".chezmoiignore",
+            ],
+        );
+        fs::write(
+            dir.join(".chezmoidata.toml"),
+            "email = \"me@example.com\"\nlist = [1]\n",
+        )
+        .unwrap();
+        let project = import(&dir, &dir.join("user"), ImportSource::Chezmoi).unwrap();
+        //The template is left out, and the renamed script keeps dot_config from being linked whole
+        assert_eq!(
+            links(&project),
+            [
+                ".bashrc: $HOME/.bashrc <- dot_bashrc",
+                "script.sh: $HOME/.config/script.sh <- dot_config/executable_script.sh",
+                "kitty: $HOME/.config/kitty <- dot_config/kitty",
+                ".ssh: $HOME/.ssh <- private_dot_ssh",
+            ]
+        );
+        assert_eq!(perms(&project, "script.sh").as_deref(), Some("u+x"));
+        assert_eq!(perms(&project, ".ssh").as_deref(), Some("go-rwx"));
+        assert_eq!(perms(&project, "kitty"), None);
+        let variables = project.variables.unwrap();
+        assert_eq!(variables.len(), 1);
+        assert_eq!(variables["email"], "me@example.com");
+    }
+
+    #[test]
+    fn yadm_alternates_become_variants() {
+        let dir = super::temp_dir("import-yadm");
+        write(
+            &dir,
+            &[
+                ".bashrc",
+                ".gitconfig",
+                ".gitconfig##os.Linux",
+                ".profile##class.work",
+                ".config/yadm/bootstrap",
+                ".config/yadm/alt/.vimrc##default",
+                ".config/yadm/alt/.vimrc##hostname.laptop",
+                ".git/HEAD",
+            ],
+        );
+        let project = import(&dir, &dir.join("user"), ImportSource::Yadm).unwrap();
+        assert_eq!(
+            links(&project),
+            [
+                ".bashrc: $HOME/.bashrc <- .bashrc",
+                ".gitconfig: $HOME/.gitconfig <- Linux=.gitconfig##os.Linux",
+                ".vimrc: $HOME/.vimrc <- .config/yadm/alt/.vimrc##default \
+                 laptop=.config/yadm/alt/.vimrc##hostname.laptop",
+            ]
+        );
+        let systems: Vec<_> = project.systems.iter().map(|x| x.to_string()).collect();
+        assert_eq!(systems, ["Linux", "laptop"]);
+    }
+}
+
+mod bootstrap {
+    use crate::actions::bootstrap;
+    use crate::config::{ProjectConfig, SystemConfig};
+    use crate::link::{Link, SourceFile, System};
+    use crate::util::{hostname, WritableConfig};
+    use crate::Args;
+    use clap::Parser;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=dots", "-c", "user.email=dots@localhost"])
+            .args(args)
+            .current_dir(dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    /// A bare repository holding a project with a variant for this machine and one for another
+    fn repository(dir: &Path, destination: &Path) -> std::path::PathBuf {
+        let host: System = hostname().unwrap().parse().unwrap();
+        let work = dir.join("work");
+        fs::create_dir(&work).unwrap();
+        let mut project = ProjectConfig::new("dotfiles".into(), &work);
+        project.systems = vec![host.clone(), "other".parse().unwrap()];
+        project.links.push(Link::new(
+            "conf".into(),
+            destination.to_string_lossy().into(),
+            SourceFile::DynamicSource {
+                default_path: None,
+                default_system: None,
+                source_map: HashMap::from([
+                    (host, "host.conf".into()),
+                    ("other".parse().unwrap(), "other.conf".into()),
+                ]),
+            },
+        ));
+        project.write_to_file(&work.join(".links.toml")).unwrap();
+        fs::write(work.join("host.conf"), "host").unwrap();
+        fs::write(work.join("other.conf"), "other").unwrap();
+        let bare = dir.join("dotfiles.git");
+        git(dir, &["init", "-q", "--bare", bare.to_str().unwrap()]);
+        git(&work, &["init", "-q"]);
+        git(&work, &["add", "."]);
+        git(&work, &["commit", "-q", "-m", "dotfiles"]);
+        git(
+            &work,
+            &["push", "-q", bare.to_str().unwrap(), "HEAD:master"],
+        );
+        git(
+            dir,
+            &[
+                "--git-dir",
+                bare.to_str().unwrap(),
+                "symbolic-ref",
+                "HEAD",
+                "refs/heads/master",
+            ],
+        );
+        bare
+    }
+
+    #[tokio::test]
+    async fn clones_and_manages_for_this_host() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("bootstrap");
+        let destination = dir.join("home/app.conf");
+        let repo = repository(&dir, &destination);
+        let config = dir.join("config.toml");
+        SystemConfig::new().write_to_file(&config).unwrap();
+        let into = dir.join("clone");
+        let args =
+            Args::try_parse_from(["dots", "--config-file", config.to_str().unwrap(), "list"])
+                .unwrap();
+
+        bootstrap(
+            args,
+            repo.to_string_lossy().into(),
+            Some(into.clone()),
+            None,
+            true,
+        )
+        .await
+        .unwrap();
+        assert!(into.join(".git").is_dir());
+        assert_eq!(fs::read_link(&destination).unwrap(), into.join("host.conf"));
+        let system_config = SystemConfig::read_from_file(&config).unwrap();
+        assert_eq!(system_config.default.as_ref(), Some(&into));
+        let projects: Vec<_> = system_config.projects.values().collect();
+        assert_eq!(projects.len(), 1);
+        assert_eq!(projects[0].path, into);
+        assert_eq!(
+            projects[0].system.as_ref().map(ToString::to_string),
+            hostname()
+        );
+    }
+}
+
+mod watch {
+    use crate::actions::watch::{handle_changes, next_changes, Watcher};
+    use crate::actions::ConflictPolicy;
+    use crate::config::{ProjectConfig, SystemConfig};
+    use crate::link::{Link, SourceFile};
+    use crate::util::WritableConfig;
+    use crate::Args;
+    use clap::Parser;
+    use inotify::Inotify;
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn changes_are_batched_and_kept() {
+        let dir = super::temp_dir("watch-events");
+        let ctx = super::context(&dir, ProjectConfig::new("test".into(), &dir));
+        let mut stream = Inotify::init()
+            .unwrap()
+            .into_event_stream([0; 4096])
+            .unwrap();
+        let mut watcher = Watcher::new(&stream);
+        watcher.update(&ctx).unwrap();
+        let config = dir.join(".links.toml");
+
+        //Changes made while nothing reads the stream, as during a sync, are not lost
+        fs::write(dir.join("a"), "").unwrap();
+        let writer = {
+            let dir = dir.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                fs::write(dir.join("b"), "").unwrap();
+            })
+        };
+        let changed = next_changes(&mut stream, &mut watcher, &config)
+            .await
+            .unwrap();
+        writer.await.unwrap();
+        assert_eq!(changed, HashSet::from([dir.join("a"), dir.join("b")]));
+
+        fs::create_dir(dir.join("nvim")).unwrap();
+        next_changes(&mut stream, &mut watcher, &config)
+            .await
+            .unwrap();
+        //New folders in the project are watched once the watches are updated
+        watcher.update(&ctx).unwrap();
+        fs::write(dir.join("nvim/init.lua"), "").unwrap();
+        let changed = next_changes(&mut stream, &mut watcher, &config)
+            .await
+            .unwrap();
+        assert_eq!(changed, HashSet::from([dir.join("nvim/init.lua")]));
+    }
+
+    #[tokio::test]
+    async fn changes_relink_their_links() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("watch-changes");
+        let (project_dir, home) = (dir.join("dots"), dir.join("home"));
+        fs::create_dir_all(&project_dir).unwrap();
+        fs::create_dir_all(&home).unwrap();
+        let mut project = ProjectConfig::new("test".into(), &project_dir);
+        for name in ["bashrc", "vimrc"] {
+            fs::write(project_dir.join(name), name).unwrap();
+            project.links.push(Link::new(
+                name.into(),
+                home.join(name).to_str().unwrap().into(),
+                SourceFile::Source {
+                    system: None,
+                    src: name.into(),
+                },
+            ));
+        }
+        project
+            .write_to_file(&project_dir.join(".links.toml"))
+            .unwrap();
+        let config = dir.join("config.toml");
+        SystemConfig::new().write_to_file(&config).unwrap();
+        let args = Args::try_parse_from([
+            "dots",
+            "--config-file",
+            config.to_str().unwrap(),
+            "--project-path",
+            project_dir.to_str().unwrap(),
+            "watch",
+        ])
+        .unwrap();
+        let bashrc = home.join("bashrc");
+        let changed = |path: PathBuf| HashSet::from([path]);
+
+        //Only the link whose source changed is linked
+        handle_changes(
+            &args,
+            &changed(project_dir.join("bashrc")),
+            ConflictPolicy::Report,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read_link(&bashrc).unwrap(), project_dir.join("bashrc"));
+        assert!(home.join("vimrc").symlink_metadata().is_err());
+
+        fs::remove_file(&bashrc).unwrap();
+        fs::write(&bashrc, "replaced").unwrap();
+        handle_changes(
+            &args,
+            &changed(bashrc.clone()),
+            ConflictPolicy::Report,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read_to_string(&bashrc).unwrap(), "replaced");
+        handle_changes(
+            &args,
+            &changed(bashrc.clone()),
+            ConflictPolicy::Relink,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read_link(&bashrc).unwrap(), project_dir.join("bashrc"));
+
+        fs::remove_file(&bashrc).unwrap();
+        handle_changes(
+            &args,
+            &changed(bashrc.clone()),
+            ConflictPolicy::Report,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read_link(&bashrc).unwrap(), project_dir.join("bashrc"));
+    }
+}
+
+mod diff_args {
+    use crate::{Args, Command};
+    use clap::Parser;
+
+    fn systems(args: &[&str]) -> Vec<crate::link::System> {
+        match Args::try_parse_from(args).unwrap().command {
+            Command::Diff { system, .. } => system,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn takes_up_to_two_systems() {
+        assert!(systems(&["dots", "diff", "vim"]).is_empty());
+        assert_eq!(
+            systems(&["dots", "diff", "vim", "-s", "laptop"]),
+            vec!["laptop".parse().unwrap()]
+        );
+        assert_eq!(
+            systems(&["dots", "diff", "vim", "--system", "laptop", "--system", "desktop"]),
+            vec!["laptop".parse().unwrap(), "desktop".parse().unwrap()]
+        );
+        assert!(Args::try_parse_from(["dots", "diff", "-s", "a", "-s", "b", "-s", "c"]).is_err());
+    }
+}
+
+mod diff {
+    use crate::diff::unified_diff;
+
+    #[test]
+    fn hunks_with_context() {
+        colored::control::set_override(false);
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\n";
+        assert_eq!(
+            unified_diff(old, new, "old", "new").unwrap(),
+            "--- old\n+++ new\n@@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n@@ -8,3 +8,4 @@\n h\n i\n j\n+k\n"
+        );
+        assert_eq!(unified_diff(old, old, "old", "new"), None);
+    }
+}
+
+mod ignore {
+    use crate::ignore::IgnoreRules;
+    use std::path::Path;
+
+    #[test]
+    fn gitignore_rules() {
+        let rules =
+            IgnoreRules::parse("# comment\n*.swp\ncache/\n/nvim/plugin\n!keep.swp\n").unwrap();
+        let ignored = |path: &str, is_dir| rules.is_ignored(Path::new(path), is_dir);
+        assert!(ignored("a.swp", false));
+        assert!(ignored("nvim/deep/a.swp", false));
+        assert!(!ignored("nvim/keep.swp", false));
+        assert!(ignored("app/cache", true));
+        assert!(!ignored("app/cache", false));
+        assert!(ignored("nvim/plugin", true));
+        assert!(!ignored("other/nvim/plugin", true));
+    }
+}
+
+mod relative_path {
+    use crate::file_actions::relative_path;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn between_folders() {
+        let relative = |from: &str, to: &str| relative_path(Path::new(from), Path::new(to));
+        assert_eq!(
+            relative("/home/me/.config", "/home/me/dots/nvim"),
+            PathBuf::from("../dots/nvim")
+        );
+        assert_eq!(
+            relative("/home/me", "/home/me/dots/.zshrc"),
+            PathBuf::from("dots/.zshrc")
+        );
+        assert_eq!(
+            relative("/etc/a/b", "/opt/x"),
+            PathBuf::from("../../../opt/x")
+        );
+        assert_eq!(relative("/home/me", "/home/me"), PathBuf::from("."));
+    }
+}
+
+mod completion {
+    use crate::actions::complete::{completion_kind, completion_script, Completion};
+    use clap_generate::Shell;
+
+    fn kind(line: &str) -> Option<Completion> {
+        let words: Vec<String> = line.split_whitespace().map(String::from).collect();
+        completion_kind(&words)
+    }
+
+    #[test]
+    fn completion_kinds() {
+        assert_eq!(kind("sync -g"), Some(Completion::Goals));
+        assert_eq!(
+            kind("--project dots revert --link"),
+            Some(Completion::Links)
+        );
+        assert_eq!(kind("-p"), Some(Completion::Projects));
+        assert_eq!(kind("status --system"), Some(Completion::Systems));
+        assert_eq!(kind("-s laptop diff"), Some(Completion::Links));
+        assert_eq!(kind("goals add-file"), Some(Completion::Goals));
+        assert_eq!(kind("goals add work"), Some(Completion::Goals));
+        assert_eq!(kind("goals add"), None);
+        assert_eq!(kind("projects set-system dots"), Some(Completion::Systems));
+        assert_eq!(kind("unlink vim"), Some(Completion::Links));
+        assert_eq!(kind("unlink -g"), Some(Completion::Goals));
+        assert_eq!(kind("sync -j"), None);
+        assert_eq!(kind("add"), None);
+    }
+
+    #[test]
+    fn fish_keeps_file_completion() {
+        //`-f` without a condition would stop fish completing paths for every argument
+        for line in completion_script(Shell::Fish).lines() {
+            if line.contains(" -f ") {
+                assert!(line.contains(" -n "), "{}", line);
+            }
+        }
+    }
+}
+
+mod history {
+    use crate::actions::undo;
+    use crate::config::SystemConfig;
+    use crate::history::{self, format_entry, parse, Entry, Operation};
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn appended_entries() {
+        let entries = vec![
+            Entry {
+                id: 1,
+                time: 0,
+                command: "add .vimrc".into(),
+                undoes: None,
+                operations: vec![
+                    Operation::Move {
+                        from: PathBuf::from("/home/user/.vimrc"),
+                        to: PathBuf::from("/home/user/dots/.vimrc"),
+                    },
+                    Operation::Config {
+                        path: PathBuf::from("/home/user/dots/.links.toml"),
+                        before: None,
+                        after: "links = []\n".into(),
+                    },
+                ],
+            },
+            Entry {
+                id: 2,
+                time: 1,
+                command: "undo".into(),
+                undoes: Some(vec![1]),
+                operations: Vec::new(),
+            },
+        ];
+        let text: String = entries
+            .iter()
+            .map(|x| format_entry(x.clone()).unwrap() + "\n")
+            .collect();
+        assert_eq!(parse(&text).unwrap(), entries);
+    }
+
+    #[tokio::test]
+    async fn undo_records_what_it_changed() {
+        let _lock = super::lock_data().await;
+        let dir = super::temp_dir("undo");
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        let (link, created) = (dir.join("link"), dir.join("created"));
+        let config = dir.join(".links.toml");
+        std::os::unix::fs::symlink(&new, &link).unwrap();
+        fs::write(&created, "created").unwrap();
+        fs::write(&config, "links = []\n").unwrap();
+        //Anything other tests left recorded is written first, so it isn't undone
+        history::commit().unwrap();
+        history::append(
+            "sync".into(),
+            None,
+            vec![
+                Operation::Retarget {
+                    path: link.clone(),
+                    from: old.clone(),
+                },
+                Operation::Create {
+                    path: created.clone(),
+                },
+                Operation::Config {
+                    path: config.clone(),
+                    before: None,
+                    after: "links = []\n".into(),
+                },
+            ],
+        )
+        .unwrap();
+        let id = history::entries().unwrap().last().unwrap().id;
+
+        undo(&SystemConfig::new(), false, 1, true).await.unwrap();
+        assert_eq!(fs::read_link(&link).unwrap(), old);
+        assert!(!created.exists());
+        assert!(!config.exists());
+        let entry = history::entries().unwrap().pop().unwrap();
+        assert_eq!(entry.undoes, Some(vec![id]));
+        assert_eq!(entry.operations.len(), 3);
+        assert!(matches!(
+            &entry.operations[0],
+            Operation::Backup { path, .. } if path == &config
+        ));
+        assert!(matches!(
+            &entry.operations[1],
+            Operation::Backup { path, .. } if path == &created
+        ));
+        assert_eq!(
+            entry.operations[2],
+            Operation::Retarget {
+                path: link,
+                from: new
+            }
+        );
+    }
+}
+
 //mod goals {
 //    use crate::goals::Goal;
 //
@@ -99,3 +99,37 @@ where
     command.args(args).stdin(Stdio::inherit());
     command
 }
+
+/// Replace a leading `~` with `$HOME`, so it is expanded like any other variable
+pub fn expand_tilde(location: &str) -> String {
+    match location.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("$HOME{}", rest),
+        _ => location.to_string(),
+    }
+}
+
+/// Ask the user a question, returning the trimmed answer
+pub fn ask(prompt: &str) -> Result<String> {
+    use std::io::Write;
+    print!("{} ", prompt);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+/// Ask the user a yes or no question, defaulting to no
+pub fn confirm(prompt: &str) -> Result<bool> {
+    let answer = ask(&format!("{} [y/N]", prompt))?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Name of this machine
+pub fn hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } != 0 {
+        return None;
+    }
+    let end = buffer.iter().position(|x| *x == 0)?;
+    Some(String::from_utf8_lossy(&buffer[..end]).into())
+}